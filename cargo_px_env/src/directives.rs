@@ -0,0 +1,91 @@
+//! Report diagnostics and declare inputs to `cargo px` from a code generator.
+//!
+//! `cargo px` parses the stdout of code generators, looking for lines that start with
+//! `cargo-px:`—in the same way `cargo` does with `cargo:` lines printed by build scripts.
+//! The functions in this module print those lines for you.
+//!
+//! ```rust,no_run
+//! use std::path::Path;
+//! use cargo_px_env::directives::{error_at, rerun_if_changed, warning, Location};
+//!
+//! rerun_if_changed("api.yaml");
+//! warning("`api.yaml` uses a deprecated schema version");
+//! error_at(
+//!     Location::new(Path::new("api.yaml"), 12, 3),
+//!     "unknown type `Foo`",
+//! );
+//! ```
+use std::fmt::Display;
+use std::path::Path;
+
+/// A position in one of the inputs of the code generator.
+///
+/// Relative paths are interpreted with respect to the working directory of the code generator.
+#[derive(Debug, Clone, Copy)]
+pub struct Location<'a> {
+    file: &'a Path,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Location<'a> {
+    /// `line` and `column` are 1-based, as in compiler diagnostics.
+    pub fn new(file: &'a Path, line: u32, column: u32) -> Self {
+        Self { file, line, column }
+    }
+}
+
+/// Emit a warning. `cargo px` will show it to the user, but code generation will succeed.
+pub fn warning(message: impl Display) {
+    directive("warning", None, message);
+}
+
+/// Emit a warning attached to a position in one of the inputs of the code generator.
+pub fn warning_at(location: Location<'_>, message: impl Display) {
+    directive("warning", Some(location), message);
+}
+
+/// Emit an error. `cargo px` will consider code generation to have failed, even if the
+/// code generator exits successfully.
+pub fn error(message: impl Display) {
+    directive("error", None, message);
+}
+
+/// Emit an error attached to a position in one of the inputs of the code generator.
+pub fn error_at(location: Location<'_>, message: impl Display) {
+    directive("error", Some(location), message);
+}
+
+/// Tell `cargo px` that the code generator only needs to be invoked again if the file
+/// or directory at `path` changes.
+///
+/// If a code generator never calls this function nor [`rerun_if_env_changed`],
+/// it's invoked every time.
+/// Relative paths are interpreted with respect to the working directory of the code generator.
+pub fn rerun_if_changed(path: impl AsRef<Path>) {
+    println!("cargo-px:rerun-if-changed={}", path.as_ref().display());
+}
+
+/// Tell `cargo px` that the code generator only needs to be invoked again if the value of
+/// the environment variable `name` changes.
+///
+/// See [`rerun_if_changed`] for more details.
+pub fn rerun_if_env_changed(name: &str) {
+    println!("cargo-px:rerun-if-env-changed={name}");
+}
+
+/// Print a `cargo-px:<key>=[file:line:column:]message` line to stdout.
+///
+/// Every directive must fit on a single line: newlines in `message` are replaced with spaces.
+fn directive(key: &str, location: Option<Location<'_>>, message: impl Display) {
+    let message = message.to_string().replace(['\r', '\n'], " ");
+    match location {
+        Some(Location { file, line, column }) => {
+            println!(
+                "cargo-px:{key}={}:{line}:{column}:{message}",
+                file.display()
+            )
+        }
+        None => println!("cargo-px:{key}={message}"),
+    }
+}
@@ -3,6 +3,7 @@ use std::path::PathBuf;
 
 use crate::error::{InvalidUnicodeError, MissingVarError, VarError};
 
+pub mod directives;
 pub mod error;
 
 /// The name of the environment variable that contains the path to the root directory
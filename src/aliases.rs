@@ -0,0 +1,142 @@
+//! Expand [`cargo` aliases](https://doc.rust-lang.org/cargo/reference/config.html#alias),
+//! so that we can reason about the command that `cargo` will actually execute.
+use std::path::Path;
+
+use crate::cargo_config::CargoConfig;
+
+/// Built-in `cargo` commands can't be shadowed by user-defined aliases.
+const BUILTIN_COMMANDS: &[&str] = &[
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "config",
+    "doc",
+    "fetch",
+    "fix",
+    "generate-lockfile",
+    "help",
+    "info",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "logout",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "read-manifest",
+    "remove",
+    "report",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+    "vendor",
+    "verify-project",
+    "version",
+    "yank",
+];
+
+/// Expand the `cargo` command in `args` if it's an alias defined in `cargo`'s configuration.
+///
+/// Aliases are expanded recursively, just like `cargo` does.
+pub(crate) fn expand_aliases(
+    args: &[String],
+    working_directory: &Path,
+) -> Result<Vec<String>, anyhow::Error> {
+    let config = CargoConfig::load(working_directory);
+    expand(args, |name| config.get(&format!("alias.{name}")))
+}
+
+fn expand(
+    args: &[String],
+    lookup: impl Fn(&str) -> Option<toml::Value>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut args = args.to_vec();
+    let mut expanded: Vec<String> = Vec::new();
+    // Skip `px`
+    while let Some(command) = args.get(1) {
+        if BUILTIN_COMMANDS.contains(&command.as_str()) {
+            break;
+        }
+        let Some(alias) = lookup(command) else {
+            break;
+        };
+        if expanded.contains(command) {
+            expanded.push(command.to_owned());
+            anyhow::bail!(
+                "Alias `{}` has an unresolvable recursive definition: {}",
+                expanded[0],
+                expanded.join(" -> ")
+            );
+        }
+        let expansion: Vec<String> = match alias {
+            toml::Value::String(alias) => alias.split_whitespace().map(ToOwned::to_owned).collect(),
+            toml::Value::Array(alias) => alias
+                .into_iter()
+                .map(|arg| match arg {
+                    toml::Value::String(arg) => Ok(arg),
+                    _ => Err(anyhow::anyhow!(
+                        "The definition of the `{command}` alias must only contain strings"
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => anyhow::bail!(
+                "The `{command}` alias must be defined as a string or an array of strings"
+            ),
+        };
+        if expansion.is_empty() {
+            anyhow::bail!("The `{command}` alias has an empty definition");
+        }
+        tracing::debug!(alias = %command, ?expansion, "Expanded a `cargo` alias");
+        expanded.push(command.to_owned());
+        args.splice(1..2, expansion);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo_args::tests::args;
+
+    fn lookup(name: &str) -> Option<toml::Value> {
+        match name {
+            "ci" => Some("t --workspace --all-features".into()),
+            "t" => Some(toml::Value::Array(vec!["test".into(), "-q".into()])),
+            "build" => Some("check".into()),
+            "loop" => Some("again".into()),
+            "again" => Some("loop".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expands_aliases_recursively() {
+        let expanded = expand(&args("px ci -p foo"), lookup).unwrap();
+        assert_eq!(
+            expanded,
+            args("px test -q --workspace --all-features -p foo")
+        );
+    }
+
+    #[test]
+    fn builtin_commands_cannot_be_shadowed() {
+        let expanded = expand(&args("px build"), lookup).unwrap();
+        assert_eq!(expanded, args("px build"));
+    }
+
+    #[test]
+    fn recursive_aliases_are_rejected() {
+        assert!(expand(&args("px loop"), lookup).is_err());
+    }
+}
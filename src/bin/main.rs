@@ -1,5 +1,5 @@
 use anyhow::Context;
-use cargo_px::{Shell, Verbosity};
+use cargo_px::{Cargo, GlobalArgs, MessageFormat, Shell};
 use std::process::{exit, Command};
 use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
@@ -45,27 +45,78 @@ fn main() {
     );
     let mut args = std::env::args();
     args.next(); // Skip the first argument, since it's always `cargo`
-    let args: Vec<_> = args.collect();
+    let mut args: Vec<_> = args.collect();
+    // `--px-message-format` is our own flag: it must not be forwarded to `cargo`.
+    let message_format = match MessageFormat::extract(&mut args) {
+        Ok(message_format) => message_format,
+        Err(e) => {
+            let _ = display_error(&e, &mut shell);
+            exit(1);
+        }
+    };
     // Skip the `px` argument.
     let forwarded_args = &args[1..];
 
-    let be_quiet = forwarded_args
+    // `cargo` accepts global options before the subcommand (e.g. `cargo px --locked build`).
+    // We set them aside and normalise the invocation to `px <sub_command> [...]`.
+    let (global_args, command_args) = GlobalArgs::parse(forwarded_args);
+    let px_args: Vec<String> = std::iter::once("px".to_owned())
+        .chain(command_args.iter().cloned())
+        .collect();
+
+    let mut cwd = std::env::current_dir().expect("Failed to get current working directory");
+    if let Some(directory) = global_args.directory() {
+        cwd = cwd.join(directory);
+    }
+    // We reason about the command that `cargo` will actually execute, after alias expansion.
+    // The original arguments are forwarded to `cargo` unchanged.
+    let expanded_args = cargo_px::expand_aliases(&px_args, &cwd).unwrap_or_else(|e| {
+        // `cargo` will report the malformed alias when we forward the command to it.
+        tracing::debug!(error = ?e, "Failed to expand `cargo` aliases");
+        px_args.clone()
+    });
+
+    // Skip `px <sub_command>`
+    let cargo = Cargo::new(
+        &cargo_path,
+        &global_args,
+        expanded_args.get(2..).unwrap_or_default(),
+    );
+
+    // `-v`, `-q` and `--color` are global options: they can appear before or after the subcommand.
+    let shell_args: Vec<String> = global_args
+        .args()
         .iter()
-        .any(|arg| arg == "--quiet" || arg == "-q");
-    if be_quiet {
-        shell.set_verbosity(Verbosity::Quiet);
+        .chain(&expanded_args[1..])
+        .cloned()
+        .collect();
+    if let Err(e) = cargo_px::configure_shell(&mut shell, &shell_args, &cwd) {
+        // `cargo` will report the invalid settings when we forward the command to it.
+        tracing::debug!(error = ?e, "Failed to configure the shell");
     }
 
     let mut has_codegened = false;
-    let cwd = std::env::current_dir().expect("Failed to get current working directory");
-    if let Some(cargo_command) = forwarded_args.first() {
+    if let Some(cargo_command) = command_args.first() {
         // This is not a proxy for a `cargo` command, it is a `cargo-px` command.
         if "verify-freshness" == cargo_command.as_str() {
-            if let Err(errors) = cargo_px::verify(&cargo_path, &cwd, &args, &mut shell) {
+            if let Err(errors) =
+                cargo_px::verify(&cargo, &cwd, &px_args, message_format, &mut shell)
+            {
                 for error in errors {
                     let _ = display_error(&error, &mut shell);
                 }
-                exit(1);
+                exit_after_failure();
+            }
+
+            exit(0);
+        }
+
+        if "plan" == cargo_command.as_str() {
+            if let Err(errors) = cargo_px::plan(&cargo, &cwd, &px_args, &mut shell) {
+                for error in errors {
+                    let _ = display_error(&error, &mut shell);
+                }
+                exit_after_failure();
             }
 
             exit(0);
@@ -73,38 +124,65 @@ fn main() {
 
         // If the user is invoking a command whose outcome might be affected by code generation,
         // we need to perform code generation first.
-        if [
-            "build", "b", "test", "t", "check", "c", "run", "r", "doc", "d", "bench", "publish",
-        ]
-        .contains(&cargo_command.as_str())
-        {
-            if let Err(errors) = cargo_px::codegen(&cargo_path, &cwd, &args, &mut shell) {
+        if cargo_px::requires_codegen(&expanded_args, &cwd) {
+            if let Err(errors) =
+                cargo_px::codegen(&cargo, &cwd, &expanded_args, message_format, &mut shell)
+            {
                 for error in errors {
                     let _ = display_error(&error, &mut shell);
                 }
-                exit(1);
+                exit_after_failure();
             }
             has_codegened = true;
         }
     }
 
     if has_codegened {
-        if let Some(cargo_command) = forwarded_args.first() {
+        if let Some(cargo_command) = command_args.first() {
             let _ = shell.status("Invoking", format!("`cargo {cargo_command}`"));
         }
     }
 
+    // Don't start `cargo` if the user asked us to stop while we were wrapping up code generation.
+    if cargo_px::interrupted().is_some() {
+        exit_after_failure();
+    }
+
     let mut cmd = Command::new(cargo_path);
     cmd.args(forwarded_args);
-    let status = match cmd.status().context("Failed to execute `cargo` command") {
-        Ok(status) => status,
-        Err(e) => {
-            let _ = display_error(&e, &mut shell);
-            exit(1);
-        }
-    };
 
-    exit(status.code().unwrap_or(1));
+    // On Unix, we replace the current process with `cargo`: exit codes, signals and
+    // job control behave exactly as if `cargo` had been invoked directly.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        let e = anyhow::Error::from(cmd.exec()).context("Failed to execute `cargo` command");
+        let _ = display_error(&e, &mut shell);
+        exit(1);
+    }
+
+    #[cfg(not(unix))]
+    {
+        let status = match cmd.status().context("Failed to execute `cargo` command") {
+            Ok(status) => status,
+            Err(e) => {
+                let _ = display_error(&e, &mut shell);
+                exit(1);
+            }
+        };
+        exit(status.code().unwrap_or(1));
+    }
+}
+
+/// Exit after `cargo px` failed, before invoking `cargo`.
+///
+/// If we were interrupted by a signal, we terminate with the same signal, as `cargo` would.
+fn exit_after_failure() -> ! {
+    match cargo_px::interrupted() {
+        Some(signal) => cargo_px::exit_with_signal(signal),
+        None => exit(1),
+    }
 }
 
 fn display_error(error: &anyhow::Error, shell: &mut Shell) -> Result<(), anyhow::Error> {
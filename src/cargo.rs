@@ -0,0 +1,74 @@
+//! Spawn the `cargo` commands that `cargo px` executes on the user's behalf—e.g.
+//! `cargo metadata` or building a code generator.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cargo_args::{has_flag, GlobalArgs};
+
+/// The `cargo` executable, configured with the global options of the current invocation.
+#[derive(Debug, Clone)]
+pub struct Cargo {
+    path: PathBuf,
+    /// Options that must be passed to every `cargo` command (e.g. `--locked`).
+    args: Vec<String>,
+    /// The directory that `cargo` was asked to change to via `-C`.
+    ///
+    /// `-C` is unstable, so we don't forward it: we spawn our `cargo` commands from
+    /// that directory instead, which has the same effect.
+    directory: Option<PathBuf>,
+}
+
+impl Cargo {
+    /// Use the `cargo` executable at `path`, honouring the global options that the user
+    /// passed before the subcommand.
+    ///
+    /// `--offline`, `--locked` and `--frozen` are honoured even if they were passed after the
+    /// subcommand (i.e. in `command_args`), as is the `CARGO_NET_OFFLINE` environment variable:
+    /// none of the `cargo` commands we execute should hit the network or touch `Cargo.lock`
+    /// if the user asked `cargo` not to.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        global_args: &GlobalArgs,
+        command_args: &[String],
+    ) -> Self {
+        let mut args = global_args.propagated().to_vec();
+        for flag in ["offline", "locked", "frozen"] {
+            let is_set = has_flag(command_args, None, flag)
+                || (flag == "offline" && is_env_enabled("CARGO_NET_OFFLINE"));
+            let option = format!("--{flag}");
+            if is_set && !args.contains(&option) {
+                args.push(option);
+            }
+        }
+        Self {
+            path: path.into(),
+            args,
+            directory: global_args.directory().map(Path::to_path_buf),
+        }
+    }
+
+    /// A `cargo` command, ready to receive a subcommand and its arguments.
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(&self.args);
+        if let Some(directory) = &self.directory {
+            cmd.current_dir(directory);
+        }
+        cmd
+    }
+
+    /// A `cargo metadata` command.
+    pub(crate) fn metadata_command(&self) -> guppy::MetadataCommand {
+        let mut cmd = guppy::MetadataCommand::new();
+        cmd.cargo_path(&self.path).other_options(self.args.clone());
+        if let Some(directory) = &self.directory {
+            cmd.current_dir(directory);
+        }
+        cmd
+    }
+}
+
+/// Check if a boolean environment variable is set to `true`, following `cargo`'s conventions.
+fn is_env_enabled(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value == "true" || value == "1")
+}
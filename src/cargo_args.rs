@@ -0,0 +1,188 @@
+//! Helpers to extract the values of specific flags from a `cargo` command line.
+//!
+//! `cargo` accepts a lot of options and we only care about a handful of them.
+//! Rather than mirroring `cargo`'s full CLI definition, we scan the arguments leniently:
+//! anything we don't recognise is ignored.
+//!
+//! We used to rely on `clap` to extract `-p`/`--package`, but a parser only knows about the
+//! flags it has been told about: it rejects the whole command line as soon as it encounters
+//! any other flag (e.g. `--release`), causing us to fall back to generating everything.
+use std::path::{Path, PathBuf};
+
+/// The global options that `cargo` accepts before the subcommand—e.g. `--locked` in
+/// `cargo --locked build`.
+#[derive(Debug, Default, Clone)]
+pub struct GlobalArgs {
+    /// All the global options, verbatim.
+    args: Vec<String>,
+    /// The global options that must be passed to every `cargo` command executed by `cargo px`.
+    propagated: Vec<String>,
+    /// The directory passed via `-C`, if any.
+    directory: Option<PathBuf>,
+}
+
+impl GlobalArgs {
+    /// Split a `cargo` command line (without the leading `cargo`) into its global options and
+    /// the rest of the invocation, starting from the subcommand.
+    pub fn parse(args: &[String]) -> (Self, &[String]) {
+        let mut global_args = GlobalArgs::default();
+        let mut i = 0;
+        while let Some(arg) = args.get(i) {
+            if !arg.starts_with('-') || arg == "--" {
+                break;
+            }
+            i += 1;
+            // Normalise `--name=value`, `-Zvalue` and `-Cvalue` into a name and an inline value.
+            let (name, inline_value) = if arg.starts_with("--") {
+                match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                }
+            } else if arg.len() > 2 && (arg.starts_with("-Z") || arg.starts_with("-C")) {
+                (&arg[..2], Some(&arg[2..]))
+            } else {
+                (arg.as_str(), None)
+            };
+            let takes_value = matches!(name, "--color" | "--config" | "--explain" | "-C" | "-Z");
+            let value = match inline_value {
+                Some(value) => Some(value.to_owned()),
+                None if takes_value => {
+                    let value = args.get(i).cloned();
+                    i += 1;
+                    value
+                }
+                None => None,
+            };
+
+            global_args
+                .args
+                .extend(args[..i].iter().skip(global_args.args.len()).cloned());
+            match (name, value) {
+                ("--locked" | "--offline" | "--frozen", _) => {
+                    global_args.propagated.push(name.to_owned());
+                }
+                ("--config" | "-Z", Some(value)) => {
+                    global_args.propagated.push(name.to_owned());
+                    global_args.propagated.push(value);
+                }
+                ("-C", Some(directory)) => global_args.directory = Some(directory.into()),
+                _ => {}
+            }
+        }
+        (global_args, &args[i..])
+    }
+
+    /// All the global options, verbatim.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The directory `cargo` was asked to change to before executing the subcommand (`-C`).
+    pub fn directory(&self) -> Option<&Path> {
+        self.directory.as_deref()
+    }
+
+    /// The global options that must be passed to every `cargo` command executed by `cargo px`:
+    /// `--locked`, `--offline`, `--frozen`, `--config` and `-Z`.
+    pub(crate) fn propagated(&self) -> &[String] {
+        &self.propagated
+    }
+}
+
+/// Return all the values passed to the flag identified by `short` and/or `long`.
+///
+/// The following forms are recognised:
+///
+/// - `--long value` and `--long=value`
+/// - `-s value` and `-svalue`
+///
+/// Scanning stops at `--`, since everything after it is meant for the invoked binary.
+pub(crate) fn flag_values(args: &[String], short: Option<char>, long: &str) -> Vec<String> {
+    let long = format!("--{long}");
+    let short = short.map(|s| format!("-{s}"));
+    let mut values = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == &long || Some(arg) == short.as_ref() {
+            if let Some(value) = args.next() {
+                values.push(value.to_owned());
+            }
+        } else if let Some(value) = arg
+            .strip_prefix(&long)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            values.push(value.to_owned());
+        } else if let Some(value) = short
+            .as_ref()
+            .and_then(|short| arg.strip_prefix(short.as_str()))
+            .filter(|value| !value.is_empty() && !arg.starts_with("--"))
+        {
+            values.push(value.trim_start_matches('=').to_owned());
+        }
+    }
+    values
+}
+
+/// Return the value of the last occurrence of the flag identified by `short` and/or `long`.
+pub(crate) fn flag_value(args: &[String], short: Option<char>, long: &str) -> Option<String> {
+    flag_values(args, short, long).pop()
+}
+
+/// Check if the flag identified by `short` and/or `long` has been passed.
+///
+/// Scanning stops at `--`, since everything after it is meant for the invoked binary.
+pub(crate) fn has_flag(args: &[String], short: Option<char>, long: &str) -> bool {
+    let long = format!("--{long}");
+    let short = short.map(|s| format!("-{s}"));
+    args.iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == &long || Some(arg) == short.as_ref())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Split a command line on whitespace—enough for the arguments used in tests.
+    pub(crate) fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(ToOwned::to_owned).collect()
+    }
+
+    #[test]
+    fn extracts_all_supported_forms() {
+        let args = args("--release -p a --package b -pc --package=d -p=e -- -p f");
+        assert_eq!(
+            flag_values(&args, Some('p'), "package"),
+            vec!["a", "b", "c", "d", "e"]
+        );
+    }
+
+    #[test]
+    fn splits_global_options_from_the_subcommand() {
+        let args = args("--locked -Zunstable-options -C dir --config=a=1 -v build -p foo");
+        let (global_args, rest) = GlobalArgs::parse(&args);
+        assert_eq!(
+            global_args.args(),
+            &args[..6],
+            "All global options should be preserved verbatim"
+        );
+        assert_eq!(
+            global_args.propagated(),
+            ["--locked", "-Z", "unstable-options", "--config", "a=1"]
+        );
+        assert_eq!(global_args.directory(), Some(Path::new("dir")));
+        assert_eq!(rest, ["build", "-p", "foo"]);
+    }
+
+    #[test]
+    fn does_not_confuse_long_flags_with_common_prefixes() {
+        let args = args("--manifest-path-extra x --manifest-path a/Cargo.toml");
+        assert_eq!(
+            flag_value(&args, None, "manifest-path").as_deref(),
+            Some("a/Cargo.toml")
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! A best-effort loader for `cargo`'s [configuration files](https://doc.rust-lang.org/cargo/reference/config.html).
+//!
+//! We only need to read a handful of keys, so we don't try to replicate `cargo`'s full
+//! configuration model: we load the hierarchy of `config.toml` files and let the
+//! closest one win, with environment variables taking precedence over files.
+use std::path::{Path, PathBuf};
+
+/// The configuration files that apply to the current invocation.
+#[derive(Debug, Default)]
+pub(crate) struct CargoConfig {
+    /// The parsed configuration files, ordered from highest to lowest precedence.
+    layers: Vec<(PathBuf, toml::Table)>,
+}
+
+impl CargoConfig {
+    /// Load all the configuration files that `cargo` would consider when invoked
+    /// from `working_directory`.
+    ///
+    /// Files that can't be read or parsed are skipped.
+    pub(crate) fn load(working_directory: &Path) -> Self {
+        let mut config_dirs: Vec<PathBuf> = working_directory
+            .ancestors()
+            .map(|dir| dir.join(".cargo"))
+            .collect();
+        if let Ok(cargo_home) = home::cargo_home() {
+            if !config_dirs.contains(&cargo_home) {
+                config_dirs.push(cargo_home);
+            }
+        }
+
+        let mut layers = Vec::new();
+        for config_dir in config_dirs {
+            // `config` is the legacy name, used only if `config.toml` is missing.
+            let Some(path) = ["config.toml", "config"]
+                .into_iter()
+                .map(|name| config_dir.join(name))
+                .find(|path| path.is_file())
+            else {
+                continue;
+            };
+            let table = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| Ok(contents.parse::<toml::Table>()?));
+            match table {
+                Ok(table) => layers.push((path, table)),
+                Err(e) => {
+                    tracing::debug!(error = ?e, path = %path.display(), "Failed to load a `cargo` configuration file")
+                }
+            }
+        }
+        Self { layers }
+    }
+
+    /// Retrieve the value for a dotted configuration key (e.g. `build.target`).
+    ///
+    /// The corresponding environment variable (e.g. `CARGO_BUILD_TARGET`) takes precedence
+    /// over configuration files.
+    pub(crate) fn get(&self, key: &str) -> Option<toml::Value> {
+        let env_key = format!("CARGO_{}", key.to_uppercase().replace(['.', '-'], "_"));
+        if let Ok(value) = std::env::var(env_key) {
+            return Some(toml::Value::String(value));
+        }
+        self.layers.iter().find_map(|(_, table)| {
+            let mut segments = key.split('.');
+            let mut value = table.get(segments.next()?)?;
+            for segment in segments {
+                value = value.as_table()?.get(segment)?;
+            }
+            Some(value.clone())
+        })
+    }
+}
@@ -7,50 +7,71 @@ use guppy::{
 };
 use petgraph::{
     stable_graph::{IndexType, NodeIndex, StableDiGraph},
-    visit::DfsPostOrder,
-    Direction::Incoming,
+    visit::{DfsPostOrder, EdgeFiltered, EdgeRef, IntoNeighborsDirected},
+    Direction::{Incoming, Outgoing},
 };
 
 use crate::codegen_unit::CodegenUnit;
-
-/// Return a codegen plan: a vector of codegen units in an order that takes into account
-/// their respective dependency relationships—i.e. you can safely invoke them in order
-/// and each codegen unit will be generated after all the codegen units it depends on.
-pub(crate) fn codegen_plan<'graph>(
-    codegen_units: Vec<CodegenUnit<'graph>>,
-    package_graph: &'graph PackageGraph,
-) -> Result<Vec<CodegenUnit<'graph>>, Vec<anyhow::Error>> {
-    Ok(AugmentedPackageGraph::new(codegen_units, package_graph)?.codegen_plan())
-}
+use crate::resolve::TargetPlatforms;
 
 #[derive(Debug)]
 /// A dependency graph augmented with information about the code generation units.
-/// In particular, an `A -> B` edge means that `A` depends on `B` via a non-dev dependency.
+/// In particular, an `A -> B` edge means that `A` depends on `B` via a non-dev dependency
+/// or, if `A` is one of the dev targets, via a dev-dependency.
 ///
 /// The graph includes all packages defined in the workspace and packages that depend on
 /// a workspace crate.  
 /// It is therefore likely to be much smaller than the [`PackageGraph`] it was built from.
-struct AugmentedPackageGraph<'graph> {
+pub(crate) struct AugmentedPackageGraph<'graph> {
     /// The dependency graph.
     dep_graph: StableDiGraph<PackageId, EdgeMetadata<'graph>>,
+    /// A map from package ID to node ID in the dependency graph.
+    pkg_id2node_id: HashMap<PackageId, NodeIndex>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum EdgeMetadata<'graph> {
     DependsOn,
+    /// Dev-dependencies are allowed to form cycles, so these edges are ignored
+    /// when looking for cyclic dependencies.
+    DevDependsOn,
     IsGeneratedBy(CodegenUnit<'graph>),
 }
 
 impl<'graph> AugmentedPackageGraph<'graph> {
-    fn new(
+    /// Build the graph for the given codegen units.
+    ///
+    /// The dev-dependencies of the packages in `dev_targets` are taken into account as well.
+    /// Dependencies that are disabled on all the target `platforms` are ignored.
+    pub(crate) fn new(
         codegen_units: Vec<CodegenUnit<'graph>>,
         package_graph: &'graph PackageGraph,
+        dev_targets: &[PackageId],
+        platforms: &TargetPlatforms,
     ) -> Result<Self, Vec<anyhow::Error>> {
         // A map from package ID to node ID in the dependency graph.
         let mut pkg_id2node_id = HashMap::new();
         let mut processed_pkg_ids = HashSet::new();
         let mut dep_graph =
             petgraph::stable_graph::StableDiGraph::<PackageId, EdgeMetadata<'graph>>::new();
+        // Generators are built for the host, regardless of `--target`: the dependencies of
+        // generator packages (and of their own dependencies) are evaluated against the host
+        // platform too.
+        let host_platforms = platforms.host_only();
+        let host_packages: HashSet<PackageId> = package_graph
+            .query_forward(
+                codegen_units
+                    .iter()
+                    .map(|unit| unit.generator.binary.package_id),
+            )
+            .map(|query| {
+                query
+                    .resolve_with_fn(|_, link| !link.dev_only())
+                    .package_ids(DependencyDirection::Forward)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
         let mut to_be_visited = package_graph.workspace().member_ids().collect::<Vec<_>>();
         while let Some(pkg_id) = to_be_visited.pop() {
             if processed_pkg_ids.contains(&pkg_id) {
@@ -73,10 +94,22 @@ impl<'graph> AugmentedPackageGraph<'graph> {
             // to the graph that does not depend on a workspace crate.
             let pkg_deps = pkg_metadata.direct_links_directed(DependencyDirection::Reverse);
             for dep in pkg_deps {
-                if dep.dev_only() {
+                let dep_pkg_id = dep.from().id();
+                let is_enabled = platforms.is_enabled(&dep, dev_targets.contains(dep_pkg_id))
+                    || (host_packages.contains(dep_pkg_id)
+                        && host_platforms.is_enabled(&dep, false));
+                if !is_enabled {
                     continue;
                 }
-                let dep_pkg_id = dep.from().id();
+                // `cargo` only follows the dev-dependencies of the packages it was asked to
+                // test/bench, never the ones of their transitive dependencies.
+                let edge = if !dep.dev_only() {
+                    EdgeMetadata::DependsOn
+                } else if dev_targets.contains(dep_pkg_id) {
+                    EdgeMetadata::DevDependsOn
+                } else {
+                    continue;
+                };
 
                 let dep_node_id = if pkg_id2node_id.contains_key(dep_pkg_id) {
                     pkg_id2node_id[dep_pkg_id]
@@ -86,7 +119,7 @@ impl<'graph> AugmentedPackageGraph<'graph> {
                     node_id
                 };
 
-                dep_graph.update_edge(dep_node_id, node_id, EdgeMetadata::DependsOn);
+                dep_graph.update_edge(dep_node_id, node_id, edge);
 
                 if !processed_pkg_ids.contains(&dep_pkg_id) {
                     to_be_visited.push(dep_pkg_id);
@@ -107,8 +140,12 @@ impl<'graph> AugmentedPackageGraph<'graph> {
             );
         }
 
-        // Cyclic dependencies are not allowed.
-        let cycles = find_cycles(&dep_graph);
+        // Cyclic dependencies are not allowed, unless a dev-dependency is involved.
+        let non_dev_graph = dep_graph.filter_map(
+            |_, pkg_id| Some(pkg_id.clone()),
+            |_, edge| (!matches!(edge, EdgeMetadata::DevDependsOn)).then(|| edge.clone()),
+        );
+        let cycles = find_cycles(&non_dev_graph);
         if !cycles.is_empty() {
             return Err(cycles
                 .into_iter()
@@ -116,7 +153,10 @@ impl<'graph> AugmentedPackageGraph<'graph> {
                 .collect());
         }
 
-        Ok(Self { dep_graph })
+        Ok(Self {
+            dep_graph,
+            pkg_id2node_id,
+        })
     }
 
     /// Returns the set of binary invocations that need to be executed in order to build the
@@ -124,15 +164,30 @@ impl<'graph> AugmentedPackageGraph<'graph> {
     ///
     /// The returned set is ordered such that the codegen units can be built in an order that
     /// takes into account their dependency relationships.
-    pub fn codegen_plan(&self) -> Vec<CodegenUnit<'graph>> {
+    pub(crate) fn codegen_plan(&self) -> Vec<CodegenUnit<'graph>> {
         let mut codegen_plan = Vec::new();
-        let mut sources = self.dep_graph.externals(Incoming).collect::<Vec<_>>();
-        // Always true since the graph is acyclic.
-        assert!(!sources.is_empty());
-        let source_seed = sources.pop().unwrap();
-        let mut dfs = DfsPostOrder::new(&self.dep_graph, source_seed);
+        // Generators are built without dev-dependencies: dev edges never constrain the
+        // generation order, they can only introduce cycles.
+        let graph = EdgeFiltered::from_fn(&self.dep_graph, |edge| {
+            !matches!(edge.weight(), EdgeMetadata::DevDependsOn)
+        });
+        let mut sources = self
+            .dep_graph
+            .node_indices()
+            .filter(|&node_index| {
+                graph
+                    .neighbors_directed(node_index, Incoming)
+                    .next()
+                    .is_none()
+            })
+            .collect::<Vec<_>>();
+        // Always non-empty for a non-empty graph, since the filtered graph is acyclic.
+        let Some(source_seed) = sources.pop() else {
+            return codegen_plan;
+        };
+        let mut dfs = DfsPostOrder::new(&graph, source_seed);
         loop {
-            while let Some(node_index) = dfs.next(&self.dep_graph) {
+            while let Some(node_index) = dfs.next(&graph) {
                 let dependent_edges = self.dep_graph.edges_directed(node_index, Incoming);
                 for dependent_edge in dependent_edges {
                     if let EdgeMetadata::IsGeneratedBy(codegen_unit) = dependent_edge.weight() {
@@ -150,6 +205,41 @@ impl<'graph> AugmentedPackageGraph<'graph> {
 
         codegen_plan
     }
+
+    /// Returns the packages of the other codegen units that must be generated before `unit`:
+    /// the ones that it depends on, directly or transitively, and the ones its generator
+    /// depends on.
+    ///
+    /// Dev-dependencies are not followed: they don't constrain the generation order.
+    pub(crate) fn unit_dependencies(&self, unit: &CodegenUnit) -> HashSet<&PackageId> {
+        let Some(&start) = self.pkg_id2node_id.get(unit.package_metadata.id()) else {
+            return HashSet::new();
+        };
+        let mut visited = HashSet::new();
+        let mut pending = vec![start];
+        while let Some(node_index) = pending.pop() {
+            for edge in self.dep_graph.edges_directed(node_index, Outgoing) {
+                if matches!(edge.weight(), EdgeMetadata::DevDependsOn) {
+                    continue;
+                }
+                if visited.insert(edge.target()) {
+                    pending.push(edge.target());
+                }
+            }
+        }
+        visited
+            .into_iter()
+            .filter(|&node_index| node_index != start && self.is_generated(node_index))
+            .map(|node_index| &self.dep_graph[node_index])
+            .collect()
+    }
+
+    /// Check if the package at `node_index` is a codegen unit.
+    fn is_generated(&self, node_index: NodeIndex) -> bool {
+        self.dep_graph
+            .edges_directed(node_index, Outgoing)
+            .any(|edge| matches!(edge.weight(), EdgeMetadata::IsGeneratedBy(_)))
+    }
 }
 
 fn cyclic_dependency_error(
@@ -171,6 +261,7 @@ fn cyclic_dependency_error(
         let relationship = graph.edge_weight(edge_id).unwrap();
         let relationship = match relationship {
             EdgeMetadata::DependsOn => "depends on",
+            EdgeMetadata::DevDependsOn => "dev-depends on",
             EdgeMetadata::IsGeneratedBy(_) => "is generated by",
         };
         let dependency = graph[*node_id].repr();
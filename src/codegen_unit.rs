@@ -1,6 +1,9 @@
 //! Logic to retrieve and validate codegen units defined in the current workspace.
 
-use crate::config::{GenerateConfig, ManifestMetadata, PxConfig, VerifyConfig};
+use crate::cargo::Cargo;
+use crate::config::{GenerateConfig, ManifestMetadata, PxConfig, VerifyConfig, WorkspaceMetadata};
+use std::time::Duration;
+
 use anyhow::Context;
 use guppy::{
     graph::{BuildTargetKind, PackageGraph, PackageMetadata},
@@ -23,12 +26,14 @@ pub(crate) struct BinaryInvocation<'graph> {
     pub(crate) binary: WorkspaceBinary<'graph>,
     /// The arguments to be passed to the binary when invoked.
     pub(crate) args: Vec<String>,
+    /// How long the binary is allowed to run before being killed.
+    pub(crate) timeout: Option<Duration>,
 }
 
 impl<'graph> BinaryInvocation<'graph> {
     /// Build a `std::process::Command` that invokes the binary.
-    pub fn run_command(&self, cargo_path: &str, be_quiet: bool) -> std::process::Command {
-        let mut cmd = self.binary.run_command(cargo_path, be_quiet);
+    pub fn run_command(&self, cargo: &Cargo, be_quiet: bool) -> std::process::Command {
+        let mut cmd = self.binary.run_command(cargo, be_quiet);
         if !self.args.is_empty() {
             cmd.arg("--").args(&self.args);
         }
@@ -37,8 +42,8 @@ impl<'graph> BinaryInvocation<'graph> {
 
     /// Build a `std::process::Command` that builds the code generator for this
     /// codegen unit.
-    pub fn build_command(&self, cargo_path: &str, be_quiet: bool) -> std::process::Command {
-        self.binary.build_command(cargo_path, be_quiet)
+    pub fn build_command(&self, cargo: &Cargo, be_quiet: bool) -> std::process::Command {
+        self.binary.build_command(cargo, be_quiet)
     }
 }
 
@@ -54,9 +59,14 @@ pub(crate) struct WorkspaceBinary<'graph> {
 
 impl<'graph> WorkspaceBinary<'graph> {
     /// Build a `std::process::Command` that invokes the binary.
-    pub fn run_command(&self, cargo_path: &str, be_quiet: bool) -> std::process::Command {
-        let mut cmd = std::process::Command::new(cargo_path);
+    pub fn run_command(&self, cargo: &Cargo, be_quiet: bool) -> std::process::Command {
+        let mut cmd = cargo.command();
+        // We always point `cargo` at the manifest of the package that defines the binary,
+        // since `cargo px` might have been invoked from outside the workspace
+        // (e.g. via `--manifest-path`).
         cmd.arg("run")
+            .arg("--manifest-path")
+            .arg(self.package_metadata.manifest_path())
             .arg("--package")
             .arg(self.package_metadata.name())
             .arg("--bin")
@@ -68,9 +78,11 @@ impl<'graph> WorkspaceBinary<'graph> {
     }
 
     /// Build a `std::process::Command` that builds the binary.
-    pub fn build_command(&self, cargo_path: &str, be_quiet: bool) -> std::process::Command {
-        let mut cmd = std::process::Command::new(cargo_path);
+    pub fn build_command(&self, cargo: &Cargo, be_quiet: bool) -> std::process::Command {
+        let mut cmd = cargo.command();
         cmd.arg("build")
+            .arg("--manifest-path")
+            .arg(self.package_metadata.manifest_path())
             .arg("--package")
             .arg(self.package_metadata.name())
             .arg("--bin")
@@ -87,10 +99,14 @@ impl<'graph> CodegenUnit<'graph> {
     ///
     /// It returns an error if the `px_config` points to a binary that is not defined
     /// in the same workspace.
+    ///
+    /// `default_timeout` applies to the generator and the verifier, unless they
+    /// specify their own.
     pub(crate) fn new(
         px_config: PxConfig,
         pkg_metadata: PackageMetadata<'graph>,
         pkg_graph: &'graph PackageGraph,
+        default_timeout: Option<u64>,
     ) -> Result<CodegenUnit<'graph>, anyhow::Error> {
         let GenerateConfig::CargoWorkspaceBinary(gen_config) = px_config.generate;
 
@@ -132,6 +148,10 @@ impl<'graph> CodegenUnit<'graph> {
                 package_metadata: generator_package_metadata,
             },
             args: gen_config.generator_args,
+            timeout: gen_config
+                .timeout
+                .or(default_timeout)
+                .map(Duration::from_secs),
         };
 
         let mut verifier = None;
@@ -174,6 +194,10 @@ impl<'graph> CodegenUnit<'graph> {
                     package_metadata: verifier_package_metadata,
                 },
                 args: verify_config.verifier_args,
+                timeout: verify_config
+                    .timeout
+                    .or(default_timeout)
+                    .map(Duration::from_secs),
             });
         }
 
@@ -188,10 +212,19 @@ impl<'graph> CodegenUnit<'graph> {
 /// Retrieve all packages in the current workspace that require code generation.
 pub(crate) fn extract_codegen_units(
     pkg_graph: &PackageGraph,
-) -> Result<Vec<CodegenUnit>, Vec<anyhow::Error>> {
+) -> Result<Vec<CodegenUnit<'_>>, Vec<anyhow::Error>> {
     let workspace = pkg_graph.workspace();
     let mut codegen_units = vec![];
     let mut errors = vec![];
+    let workspace_metadata =
+        serde_json::from_value::<Option<WorkspaceMetadata>>(workspace.metadata_table().to_owned())
+            .context(
+                "Failed to deserialize `cargo px`'s configuration from `[workspace.metadata.px]`",
+            )
+            .map_err(|e| vec![e])?;
+    let default_timeout = workspace_metadata
+        .and_then(|metadata| metadata.px)
+        .and_then(|px| px.timeout);
     for p_metadata in workspace.iter() {
         let raw_metadata = p_metadata.metadata_table().to_owned();
         match serde_json::from_value::<Option<ManifestMetadata>>(raw_metadata) {
@@ -202,7 +235,7 @@ pub(crate) fn extract_codegen_units(
                 let Some(px_config) = metadata.px else {
                     continue;
                 };
-                match CodegenUnit::new(px_config, p_metadata, pkg_graph) {
+                match CodegenUnit::new(px_config, p_metadata, pkg_graph, default_timeout) {
                     Ok(codegen_unit) => codegen_units.push(codegen_unit),
                     Err(e) => errors.push(e),
                 }
@@ -0,0 +1,111 @@
+//! Determine which `cargo` commands must be preceded by code generation.
+use std::path::{Path, PathBuf};
+
+use crate::config::WorkspaceMetadata;
+use crate::targets::extract_manifest_path;
+
+/// The `cargo` commands whose outcome might be affected by code generation, including the
+/// most popular third-party subcommands.
+///
+/// It can be customised via `workspace.metadata.px.codegen_for`.
+const DEFAULT_CODEGEN_COMMANDS: &[&str] = &[
+    "build",
+    "b",
+    "test",
+    "t",
+    "check",
+    "c",
+    "run",
+    "r",
+    "doc",
+    "d",
+    "bench",
+    "publish",
+    "package",
+    "install",
+    "clippy",
+    "fix",
+    "nextest",
+    "llvm-cov",
+    "tarpaulin",
+    "miri",
+    "hack",
+];
+
+/// Check if code generation must be performed before forwarding the invocation to `cargo`.
+pub(crate) fn requires_codegen(args: &[String], working_directory: &Path) -> bool {
+    let Some(command) = args.get(1) else {
+        return false;
+    };
+    // `cargo install` only touches the current workspace if it's asked to install a local package.
+    if command == "install" && crate::cargo_args::flag_value(&args[2..], None, "path").is_none() {
+        return false;
+    }
+
+    let start_dir = extract_manifest_path(args, working_directory)
+        .and_then(|path| path.parent().map(ToOwned::to_owned))
+        .unwrap_or_else(|| working_directory.to_owned());
+    let overrides = find_workspace_manifest(&start_dir)
+        .and_then(|manifest_path| load_workspace_metadata(&manifest_path))
+        .and_then(|metadata| metadata.px)
+        .map(|px| px.codegen_for)
+        .unwrap_or_default();
+
+    let mut requires_codegen = DEFAULT_CODEGEN_COMMANDS.contains(&command.as_str());
+    // Later entries win over earlier ones.
+    for entry in overrides {
+        if let Some(removed) = entry.strip_prefix('!') {
+            if removed == command {
+                requires_codegen = false;
+            }
+        } else if &entry == command {
+            requires_codegen = true;
+        }
+    }
+    requires_codegen
+}
+
+/// Find the manifest of the workspace that contains `start_dir`, using the same lookup
+/// logic as `cargo`: the closest ancestor manifest that has a `[workspace]` section, unless
+/// the package manifest points to its workspace via `package.workspace`.
+///
+/// We can't rely on `cargo metadata` here: it's too slow to be invoked for every command
+/// that `cargo px` forwards.
+fn find_workspace_manifest(start_dir: &Path) -> Option<PathBuf> {
+    for dir in start_dir.ancestors() {
+        let manifest_path = dir.join("Cargo.toml");
+        let Some(manifest) = read_manifest(&manifest_path) else {
+            continue;
+        };
+        if manifest.contains_key("workspace") {
+            return Some(manifest_path);
+        }
+        let explicit_root = manifest
+            .get("package")
+            .and_then(|package| package.get("workspace"))
+            .and_then(|workspace| workspace.as_str());
+        if let Some(explicit_root) = explicit_root {
+            return Some(dir.join(explicit_root).join("Cargo.toml"));
+        }
+    }
+    None
+}
+
+/// Extract `[workspace.metadata]` from the workspace root manifest.
+fn load_workspace_metadata(manifest_path: &Path) -> Option<WorkspaceMetadata> {
+    let metadata = read_manifest(manifest_path)?
+        .remove("workspace")?
+        .as_table_mut()?
+        .remove("metadata")?;
+    match metadata.try_into() {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            tracing::debug!(error = ?e, "Failed to deserialize `[workspace.metadata.px]`");
+            None
+        }
+    }
+}
+
+fn read_manifest(manifest_path: &Path) -> Option<toml::Table> {
+    std::fs::read_to_string(manifest_path).ok()?.parse().ok()
+}
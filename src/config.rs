@@ -38,6 +38,11 @@ pub struct CargoBinaryGeneratorConfig {
     #[serde(default)]
     /// The arguments to be passed to the generator binary.
     pub(crate) generator_args: Vec<String>,
+    /// How long (in seconds) the generator is allowed to run before being killed.
+    ///
+    /// It overrides the workspace-wide default, `workspace.metadata.px.timeout`.
+    #[serde(default)]
+    pub(crate) timeout: Option<u64>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -50,4 +55,33 @@ pub struct CargoBinaryVerifierConfig {
     #[serde(default)]
     /// The arguments to be passed to the verifier binary.
     pub(crate) verifier_args: Vec<String>,
+    /// How long (in seconds) the verifier is allowed to run before being killed.
+    ///
+    /// It overrides the workspace-wide default, `workspace.metadata.px.timeout`.
+    #[serde(default)]
+    pub(crate) timeout: Option<u64>,
+}
+
+/// The configuration that `px` expects to find in the `[workspace.metadata]` section
+/// of the workspace root manifest.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub(crate) struct WorkspaceMetadata {
+    #[serde(default)]
+    pub(crate) px: Option<WorkspacePxConfig>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub(crate) struct WorkspacePxConfig {
+    /// Additions and removals to the default set of `cargo` commands that trigger code generation.
+    ///
+    /// A command name (e.g. `xtask`) adds it to the set, while a command name prefixed
+    /// with `!` (e.g. `!publish`) removes it.
+    #[serde(default)]
+    pub(crate) codegen_for: Vec<String>,
+    /// How long (in seconds) generators and verifiers are allowed to run before being killed,
+    /// unless they specify their own timeout.
+    ///
+    /// There is no timeout if it's not set.
+    #[serde(default)]
+    pub(crate) timeout: Option<u64>,
 }
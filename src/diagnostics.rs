@@ -0,0 +1,207 @@
+//! Report code generation failures as `cargo`-compatible JSON diagnostics, so that tools
+//! consuming `cargo`'s `--message-format=json` stream (e.g. rust-analyzer) can surface them.
+use guppy::graph::{BuildTargetId, BuildTargetKind, PackageMetadata};
+use serde_json::json;
+
+use crate::cargo_args::flag_value;
+use crate::shell::Shell;
+
+/// Check if the `cargo` command we're fronting emits JSON messages on stdout
+/// (e.g. `--message-format=json-diagnostic-rendered-ansi`).
+pub(crate) fn uses_json_messages(args: &[String]) -> bool {
+    flag_value(args, None, "message-format").is_some_and(|format| {
+        format
+            .split(',')
+            .any(|format| format.trim().starts_with("json"))
+    })
+}
+
+/// Emit `error` as a `compiler-message` record attached to the manifest of the package
+/// whose code generation failed.
+///
+/// The primary span points at the `[package.metadata.px]` section of the manifest, if we can
+/// find it, or at its first line otherwise.
+pub(crate) fn emit_codegen_failure(
+    shell: &mut Shell,
+    package: &PackageMetadata,
+    error: &anyhow::Error,
+) {
+    let manifest_path = package.manifest_path();
+    let span = std::fs::read_to_string(manifest_path)
+        .ok()
+        .and_then(|manifest| Span::find(&manifest))
+        .unwrap_or_default();
+    let line = span.line;
+
+    let message = error.to_string();
+    let causes: Vec<String> = error.chain().skip(1).map(ToString::to_string).collect();
+    let mut rendered = format!(
+        "error: {message}\n --> {manifest_path}:{line}:{}\n",
+        span.column_start
+    );
+    for cause in &causes {
+        rendered.push_str(&format!(
+            "  = note: {}\n",
+            cause.replace('\n', "\n          ")
+        ));
+    }
+    let children: Vec<_> = causes
+        .iter()
+        .map(|cause| {
+            json!({
+                "message": cause,
+                "code": null,
+                "level": "note",
+                "spans": [],
+                "children": [],
+                "rendered": null,
+            })
+        })
+        .collect();
+
+    let record = json!({
+        "reason": "compiler-message",
+        "package_id": package.id().repr(),
+        "manifest_path": manifest_path,
+        "target": target(package),
+        "message": {
+            "$message_type": "diagnostic",
+            "message": message,
+            "code": null,
+            "level": "error",
+            "spans": [{
+                "file_name": manifest_path,
+                "byte_start": span.byte_start,
+                "byte_end": span.byte_end,
+                "line_start": line,
+                "line_end": line,
+                "column_start": span.column_start,
+                "column_end": span.column_end,
+                "is_primary": true,
+                "text": [{
+                    "text": span.text,
+                    "highlight_start": span.column_start,
+                    "highlight_end": span.column_end,
+                }],
+                "label": "code generation failed for this package",
+                "suggested_replacement": null,
+                "suggestion_applicability": null,
+                "expansion": null,
+            }],
+            "children": children,
+            "rendered": rendered,
+        },
+    });
+    if let Err(e) = shell.print_json(&record) {
+        tracing::debug!(error = ?e, "Failed to emit a `compiler-message` record");
+    }
+}
+
+/// The location of the `[package.metadata.px...]` header in a manifest.
+///
+/// Lines and columns are 1-based and columns count characters, as in `rustc`'s diagnostics,
+/// while byte offsets are 0-based.
+#[derive(Debug, PartialEq, Eq)]
+struct Span {
+    byte_start: usize,
+    byte_end: usize,
+    line: usize,
+    column_start: usize,
+    column_end: usize,
+    /// The content of the line.
+    text: String,
+}
+
+impl Default for Span {
+    /// An empty span at the beginning of the manifest.
+    fn default() -> Self {
+        Self {
+            byte_start: 0,
+            byte_end: 0,
+            line: 1,
+            column_start: 1,
+            column_end: 1,
+            text: String::new(),
+        }
+    }
+}
+
+impl Span {
+    /// Find the first `[package.metadata.px...]` header in `manifest`.
+    fn find(manifest: &str) -> Option<Self> {
+        let mut line_offset = 0;
+        for (i, line) in manifest.split_inclusive('\n').enumerate() {
+            let text = line.trim_end_matches(['\n', '\r']);
+            let header = text.trim();
+            if header.starts_with("[package.metadata.px") {
+                let indent = text.len() - text.trim_start().len();
+                let column_start = text[..indent].chars().count() + 1;
+                return Some(Self {
+                    byte_start: line_offset + indent,
+                    byte_end: line_offset + indent + header.len(),
+                    line: i + 1,
+                    column_start,
+                    column_end: column_start + header.chars().count(),
+                    text: text.to_owned(),
+                });
+            }
+            line_offset += line.len();
+        }
+        None
+    }
+}
+
+/// Describe the main build target of `package`, in the format used by `cargo`'s JSON messages.
+fn target(package: &PackageMetadata) -> serde_json::Value {
+    let Some(target) = package
+        .build_target(&BuildTargetId::Library)
+        .or_else(|| package.build_targets().next())
+    else {
+        return serde_json::Value::Null;
+    };
+    let crate_types: Vec<String> = match target.kind() {
+        BuildTargetKind::LibraryOrExample(crate_types) => crate_types.to_vec(),
+        BuildTargetKind::ProcMacro => vec!["proc-macro".into()],
+        _ => vec!["bin".into()],
+    };
+    json!({
+        "kind": crate_types,
+        "crate_types": crate_types,
+        "name": target.name(),
+        "src_path": target.path(),
+        "edition": target.edition(),
+        "doc": true,
+        "doctest": false,
+        "test": true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo_args::tests::args;
+
+    #[test]
+    fn detects_json_message_formats() {
+        assert!(uses_json_messages(&args(
+            "--message-format=json-diagnostic-rendered-ansi"
+        )));
+        assert!(uses_json_messages(&args("--message-format short,json")));
+        assert!(!uses_json_messages(&args("--message-format human")));
+    }
+
+    #[test]
+    fn spans_point_at_the_px_section() {
+        let manifest = "[package]\r\nname = \"é\"\r\n\r\n  [package.metadata.px.generate]\r\n";
+        let span = Span::find(manifest).unwrap();
+        assert_eq!(
+            &manifest[span.byte_start..span.byte_end],
+            "[package.metadata.px.generate]"
+        );
+        assert_eq!(span.line, 4);
+        assert_eq!((span.column_start, span.column_end), (3, 33));
+        assert_eq!(span.text, "  [package.metadata.px.generate]");
+
+        assert_eq!(Span::find("[package]\nname = \"api\"\n"), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! Directives that generators and verifiers can print on stdout to talk to `cargo px`,
+//! modelled after the `cargo:` directives of build scripts—e.g. `cargo-px:warning=...`.
+//!
+//! `cargo_px_env` provides typed helpers to emit them.
+use std::path::{Path, PathBuf};
+
+use crate::shell::Shell;
+
+/// Every directive starts with this prefix.
+const PREFIX: &str = "cargo-px:";
+
+/// A directive printed by a generator or a verifier.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Directive {
+    /// `cargo-px:warning=[file:line:column:]message`
+    Warning(Diagnostic),
+    /// `cargo-px:error=[file:line:column:]message`
+    ///
+    /// The unit is considered to have failed, even if the process exits successfully.
+    Error(Diagnostic),
+    /// `cargo-px:rerun-if-changed=<path>`
+    ///
+    /// The generator only needs to be invoked again if the file or directory changes.
+    RerunIfChanged(PathBuf),
+    /// `cargo-px:rerun-if-env-changed=<VAR>`
+    ///
+    /// The generator only needs to be invoked again if the environment variable changes.
+    RerunIfEnvChanged(String),
+}
+
+/// A message, optionally attached to a location in one of the inputs of the generator.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Diagnostic {
+    pub(crate) location: Option<Location>,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub(crate) file: PathBuf,
+    pub(crate) line: u32,
+    pub(crate) column: u32,
+}
+
+impl Directive {
+    /// Parse a line of output.
+    ///
+    /// It returns `None` if the line isn't a directive, and an error if it's a directive
+    /// we don't recognise.
+    pub(crate) fn parse(line: &str) -> Option<Result<Self, anyhow::Error>> {
+        let directive = line.trim_end().strip_prefix(PREFIX)?;
+        let Some((key, value)) = directive.split_once('=') else {
+            return Some(Err(anyhow::anyhow!(
+                "`{line}` is not a valid `cargo px` directive: it should be `{PREFIX}KEY=VALUE`"
+            )));
+        };
+        let directive = match key {
+            "warning" => Directive::Warning(Diagnostic::parse(value)),
+            "error" => Directive::Error(Diagnostic::parse(value)),
+            "rerun-if-changed" => Directive::RerunIfChanged(PathBuf::from(value)),
+            "rerun-if-env-changed" => Directive::RerunIfEnvChanged(value.to_owned()),
+            _ => {
+                return Some(Err(anyhow::anyhow!(
+                    "`{key}` is not a known `cargo px` directive"
+                )))
+            }
+        };
+        Some(Ok(directive))
+    }
+
+    /// Print the directive to stderr, on behalf of `unit_name`, if it's meant for the user.
+    ///
+    /// Relative paths are interpreted with respect to `base_dir`, the working directory
+    /// of the process that emitted the directive.
+    pub(crate) fn render(&self, unit_name: &str, base_dir: &Path, shell: &mut Shell) {
+        let _ = match self {
+            Directive::Warning(diagnostic) => {
+                let message = diagnostic.display(unit_name, base_dir, shell);
+                shell.warn(message)
+            }
+            Directive::Error(diagnostic) => {
+                let message = diagnostic.display(unit_name, base_dir, shell);
+                shell.error(message)
+            }
+            Directive::RerunIfChanged(_) | Directive::RerunIfEnvChanged(_) => Ok(()),
+        };
+    }
+}
+
+impl Diagnostic {
+    /// Parse `[file:line:column:]message`.
+    ///
+    /// The file path may contain colons (e.g. `C:\...` on Windows), so we look for the first
+    /// colon that's followed by two numeric segments.
+    fn parse(value: &str) -> Self {
+        for (i, _) in value.match_indices(':').filter(|(i, _)| *i > 0) {
+            let mut parts = value[i + 1..].splitn(3, ':');
+            let (Some(line), Some(column), Some(message)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                break;
+            };
+            if let (Ok(line), Ok(column)) = (line.parse(), column.parse()) {
+                return Self {
+                    location: Some(Location {
+                        file: PathBuf::from(&value[..i]),
+                        line,
+                        column,
+                    }),
+                    message: message.trim().to_owned(),
+                };
+            }
+        }
+        Self {
+            location: None,
+            message: value.trim().to_owned(),
+        }
+    }
+
+    fn display(&self, unit_name: &str, base_dir: &Path, shell: &mut Shell) -> String {
+        let mut output = format!("{unit_name}: {}", self.message);
+        if let Some(Location { file, line, column }) = &self.location {
+            let link = shell.err_file_hyperlink(&base_dir.join(file));
+            output.push_str(&format!(
+                "\n  --> {}{}:{line}:{column}{}",
+                link.open(),
+                file.display(),
+                link.close()
+            ));
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives() {
+        assert_eq!(Directive::parse("not a directive").map(Result::ok), None);
+        assert_eq!(
+            Directive::parse("cargo-px:warning=something is off")
+                .unwrap()
+                .unwrap(),
+            Directive::Warning(Diagnostic {
+                location: None,
+                message: "something is off".into()
+            })
+        );
+        assert_eq!(
+            Directive::parse(r"cargo-px:error=C:\api.yaml:12:3: unknown type `Foo`")
+                .unwrap()
+                .unwrap(),
+            Directive::Error(Diagnostic {
+                location: Some(Location {
+                    file: r"C:\api.yaml".into(),
+                    line: 12,
+                    column: 3
+                }),
+                message: "unknown type `Foo`".into()
+            })
+        );
+        assert_eq!(
+            Directive::parse("cargo-px:rerun-if-env-changed=API_URL")
+                .unwrap()
+                .unwrap(),
+            Directive::RerunIfEnvChanged("API_URL".into())
+        );
+        assert!(Directive::parse("cargo-px:unknown=x").unwrap().is_err());
+    }
+}
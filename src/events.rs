@@ -0,0 +1,136 @@
+//! Machine-readable events, emitted on stdout when `--px-message-format=json` is passed.
+//!
+//! Each event is a JSON object on its own line, tagged by its `reason` field—the same
+//! convention used by `cargo`'s `--message-format=json`.
+//! The schema is documented in the README: fields can be added, but existing ones
+//! must never be removed or renamed.
+use serde::Serialize;
+
+use crate::cargo_args::flag_value;
+use crate::shell::Shell;
+
+/// The format used to report on the progress of code generation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Human-readable status messages on stderr.
+    #[default]
+    Human,
+    /// Human-readable status messages on stderr, JSON events on stdout.
+    Json,
+}
+
+impl MessageFormat {
+    /// Extract `--px-message-format` from `args`.
+    ///
+    /// The flag is removed from `args`, since `cargo` doesn't know about it.
+    pub fn extract(args: &mut Vec<String>) -> Result<Self, anyhow::Error> {
+        let end = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        let value = flag_value(&args[..end], None, "px-message-format");
+        let mut i = 0;
+        while i < args.len() && args[i] != "--" {
+            if args[i] == "--px-message-format" {
+                args.drain(i..(i + 2).min(args.len()));
+            } else if args[i].starts_with("--px-message-format=") {
+                args.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        match value.as_deref() {
+            None | Some("human") => Ok(MessageFormat::Human),
+            Some("json") => Ok(MessageFormat::Json),
+            Some(other) => anyhow::bail!(
+                "`{other}` is not a valid value for `--px-message-format`. \
+                Expected either `human` or `json`"
+            ),
+        }
+    }
+}
+
+/// An event in the lifecycle of a `cargo px` invocation.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub(crate) enum Event<'a> {
+    /// The codegen units that are going to be processed, in execution order.
+    PlanComputed { units: Vec<PlannedUnit<'a>> },
+    /// `cargo px` started compiling a generator or a verifier.
+    CompileStarted {
+        package: &'a str,
+        binary: &'a str,
+        role: Role,
+    },
+    /// A generator or a verifier has been compiled, successfully or not.
+    CompileFinished {
+        package: &'a str,
+        binary: &'a str,
+        role: Role,
+        success: bool,
+        /// In seconds.
+        duration: f64,
+    },
+    /// The generator for `package` has been invoked.
+    GenerationStarted { package: &'a str },
+    /// The generator for `package` wasn't invoked, since its inputs haven't changed
+    /// since its last successful run.
+    GenerationFresh { package: &'a str },
+    /// The generator for `package` has exited.
+    GenerationFinished {
+        package: &'a str,
+        success: bool,
+        /// In seconds.
+        duration: f64,
+    },
+    /// The verifier for `package` has exited.
+    ///
+    /// `success` is `false` if the verifier failed, i.e. the generated code is stale.
+    VerificationFinished {
+        package: &'a str,
+        success: bool,
+        /// In seconds.
+        duration: f64,
+    },
+}
+
+/// A codegen unit, as reported in the `plan-computed` event.
+#[derive(Debug, Serialize)]
+pub(crate) struct PlannedUnit<'a> {
+    /// The name of the generated package.
+    pub(crate) package: &'a str,
+    /// The name of the generator binary.
+    pub(crate) generator: &'a str,
+    /// The name of the verifier binary, if there is one.
+    pub(crate) verifier: Option<&'a str>,
+}
+
+/// The role of the binary being compiled.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Role {
+    Generator,
+    Verifier,
+}
+
+/// Emit `event` on stdout, if JSON output has been requested.
+pub(crate) fn emit(shell: &mut Shell, format: MessageFormat, event: &Event) {
+    if format == MessageFormat::Json {
+        if let Err(e) = shell.print_json(event) {
+            tracing::debug!(error = ?e, "Failed to emit a JSON event");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cargo_args::tests::args;
+
+    #[test]
+    fn message_format_is_removed_from_the_arguments() {
+        let mut a = args("px --px-message-format json build --px-message-format=json -- x");
+        assert_eq!(MessageFormat::extract(&mut a).unwrap(), MessageFormat::Json);
+        assert_eq!(a, args("px build -- x"));
+    }
+}
@@ -0,0 +1,271 @@
+//! Skip generators whose inputs haven't changed since their last successful run.
+//!
+//! Generators declare their inputs by printing `cargo-px:rerun-if-changed=<path>` and
+//! `cargo-px:rerun-if-env-changed=<VAR>`, in the same way build scripts do.
+//! After a successful run, we save a fingerprint of those inputs under `target/px/fingerprints`:
+//! if it still matches on the next run, the generator is not invoked.
+//! Generators that don't declare any input are always invoked.
+//!
+//! Fingerprints are saved to disk: every hash must be stable across runs and toolchains.
+//! We use FNV-1a over explicit byte representations, rather than `std::hash`.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::summary::{self, Snapshot};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Fingerprint {
+    /// When the generator executable was last modified—i.e. when it was last rebuilt.
+    executable: SystemTime,
+    args: Vec<String>,
+    /// The working directory of the generator.
+    /// The generator resolves relative paths against it: if it changes, so may the generator's
+    /// inputs, even if they're the same on our end.
+    working_directory: PathBuf,
+    /// A hash of each declared file or directory, `None` if it doesn't exist.
+    files: BTreeMap<PathBuf, Option<u64>>,
+    /// A hash of the value of each declared environment variable, `None` if it's not set.
+    /// We don't store the values themselves, since they may be secrets.
+    env: BTreeMap<String, Option<u64>>,
+    /// A hash of the files in the generated package, to catch manual edits to the output.
+    output: u64,
+}
+
+impl Fingerprint {
+    /// Fingerprint the current state of the given inputs.
+    ///
+    /// It returns `None` if the generator executable can't be found.
+    pub(crate) fn new(
+        executable: &Path,
+        args: &[String],
+        working_directory: &Path,
+        files: impl IntoIterator<Item = PathBuf>,
+        env: impl IntoIterator<Item = String>,
+        output: &Snapshot,
+    ) -> Option<Self> {
+        let executable = std::fs::metadata(executable).ok()?.modified().ok()?;
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let hash = hash_path(&path);
+                (path, hash)
+            })
+            .collect();
+        let env = env
+            .into_iter()
+            .map(|name| {
+                let hash = std::env::var_os(&name).map(|value| hash(value.as_encoded_bytes()));
+                (name, hash)
+            })
+            .collect();
+        Some(Self {
+            executable,
+            args: args.to_owned(),
+            working_directory: working_directory.to_owned(),
+            files,
+            env,
+            output: hash_snapshot(output),
+        })
+    }
+
+    /// Load the fingerprint saved at `path`, if there is one.
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let content = std::fs::read(path).ok()?;
+        serde_json::from_slice(&content)
+            .map_err(|e| {
+                tracing::debug!(error = ?e, path = %path.display(), "Failed to parse a fingerprint");
+            })
+            .ok()
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_vec(self)?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to save a fingerprint to {}", path.display()))
+    }
+
+    /// Check if the inputs recorded in this fingerprint are unchanged.
+    pub(crate) fn is_fresh(
+        &self,
+        executable: &Path,
+        args: &[String],
+        working_directory: &Path,
+        output: &Snapshot,
+    ) -> bool {
+        let current = Self::new(
+            executable,
+            args,
+            working_directory,
+            self.files.keys().cloned(),
+            self.env.keys().cloned(),
+            output,
+        );
+        current.as_ref() == Some(self)
+    }
+}
+
+/// Extract the path to the executable of `binary_name` from a JSON message emitted by
+/// `cargo build --message-format=json`, if it's the `compiler-artifact` record for it.
+pub(crate) fn executable_from_message(message: &str, binary_name: &str) -> Option<PathBuf> {
+    let message: serde_json::Value = serde_json::from_str(message).ok()?;
+    let target = &message["target"];
+    let is_binary = target["kind"]
+        .as_array()
+        .is_some_and(|kinds| kinds.iter().any(|kind| kind == "bin"));
+    if message["reason"] != "compiler-artifact" || target["name"] != binary_name || !is_binary {
+        return None;
+    }
+    message["executable"].as_str().map(PathBuf::from)
+}
+
+/// Hash `bytes` with 64-bit FNV-1a.
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// The 64-bit FNV-1a hash function, whose output only depends on the bytes it's fed.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hash the content of a file or, for a directory, of all the files it contains.
+fn hash_path(path: &Path) -> Option<u64> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if metadata.is_dir() {
+        Some(hash_snapshot(&summary::snapshot(path)))
+    } else {
+        std::fs::read(path).ok().map(|content| hash(&content))
+    }
+}
+
+/// Hash a snapshot in a way that doesn't depend on the iteration order of its entries.
+fn hash_snapshot(snapshot: &Snapshot) -> u64 {
+    let sorted: BTreeMap<_, _> = snapshot.iter().collect();
+    let mut hasher = Fnv1a::new();
+    for (path, hash) in sorted {
+        hasher.write(path.as_os_str().as_encoded_bytes());
+        // Paths can't contain NUL bytes: it unambiguously marks the end of the path.
+        hasher.write(&[0]);
+        hasher.write(&hash.to_le_bytes());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory, unique to the calling test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cargo-px-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn hashes_are_stable() {
+        // Reference values for 64-bit FNV-1a.
+        assert_eq!(hash(b""), 0xcbf29ce484222325);
+        assert_eq!(hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn fingerprints_track_inputs() {
+        let dir = scratch_dir("fingerprint");
+        let executable = dir.join("gen");
+        let input = dir.join("api.yaml");
+        std::fs::write(&executable, "").unwrap();
+        std::fs::write(&input, "v1").unwrap();
+        let env_var = "CARGO_PX_FINGERPRINT_TEST_INPUT";
+        std::env::set_var(env_var, "a");
+        let args = vec!["--quiet".to_owned()];
+        let output = Snapshot::from([(dir.join("lib.rs"), hash(b"pub fn f() {}"))]);
+
+        let fingerprint = Fingerprint::new(
+            &executable,
+            &args,
+            &dir,
+            [input.clone()],
+            [env_var.to_owned()],
+            &output,
+        )
+        .unwrap();
+        let path = dir.join("fingerprints").join("api.json");
+        fingerprint.save(&path).unwrap();
+        let fingerprint = Fingerprint::load(&path).unwrap();
+        let is_fresh = || fingerprint.is_fresh(&executable, &args, &dir, &output);
+        assert!(is_fresh());
+
+        // Changes to the inputs.
+        std::fs::write(&input, "v2").unwrap();
+        assert!(!is_fresh());
+        std::fs::write(&input, "v1").unwrap();
+        assert!(is_fresh());
+        std::env::set_var(env_var, "b");
+        assert!(!is_fresh());
+        std::env::set_var(env_var, "a");
+        assert!(is_fresh());
+
+        // Changes to the invocation or to the generated code.
+        assert!(!fingerprint.is_fresh(&executable, &[], &dir, &output));
+        assert!(!fingerprint.is_fresh(&executable, &args, &dir.join("api"), &output));
+        assert!(!fingerprint.is_fresh(&executable, &args, &dir, &Snapshot::new()));
+        assert!(!fingerprint.is_fresh(&dir.join("missing"), &args, &dir, &output));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn executables_are_found_in_compiler_artifacts() {
+        let artifact = |name: &str, kind: &str, executable: &str| {
+            format!(
+                r#"{{"reason":"compiler-artifact","target":{{"name":"{name}","kind":["{kind}"]}},"executable":{executable}}}"#
+            )
+        };
+        assert_eq!(
+            executable_from_message(&artifact("gen", "bin", r#""/ws/target/debug/gen""#), "gen"),
+            Some(PathBuf::from("/ws/target/debug/gen"))
+        );
+        assert_eq!(
+            executable_from_message(&artifact("gen", "lib", "null"), "gen"),
+            None
+        );
+        assert_eq!(
+            executable_from_message(
+                &artifact("other", "bin", r#""/ws/target/debug/other""#),
+                "gen"
+            ),
+            None
+        );
+        assert_eq!(
+            executable_from_message(r#"{"reason":"build-finished","success":true}"#, "gen"),
+            None
+        );
+    }
+}
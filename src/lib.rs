@@ -1,89 +1,393 @@
-use std::path::Path;
-use std::time::Instant;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use ahash::HashSet;
 use anyhow::Context;
+use cargo_config::CargoConfig;
 use codegen_unit::CodegenUnit;
 use guppy::graph::{PackageGraph, PackageMetadata};
+use guppy::PackageId;
+use resolve::{FeatureSelection, TargetPlatforms};
 use targets::determine_targets;
 
+use crate::codegen_plan::AugmentedPackageGraph;
 use crate::codegen_unit::{extract_codegen_units, BinaryInvocation};
-
+use crate::directives::Directive;
+use crate::events::{Event, PlannedUnit, Role};
+use crate::fingerprint::Fingerprint;
+use crate::progress::Progress;
+use crate::summary::{Summary, UnitOutcome, UnitReport};
+
+mod aliases;
+mod cargo;
+mod cargo_args;
+mod cargo_config;
 mod codegen_plan;
 mod codegen_unit;
+mod commands;
 mod config;
+mod diagnostics;
+mod directives;
+mod events;
+mod fingerprint;
+mod package_spec;
+mod process;
+mod progress;
+mod resolve;
 mod shell;
+mod summary;
 mod targets;
+mod terminal;
 
+pub use cargo::Cargo;
+pub use cargo_args::GlobalArgs;
+pub use events::MessageFormat;
+pub use process::{exit_with_signal, interrupted};
 pub use shell::{Shell, Verbosity};
 
+/// Expand the `cargo` command in `args` if it's an alias defined in `cargo`'s configuration
+/// (e.g. `[alias] ci = "test --workspace"`).
+pub fn expand_aliases(
+    args: &[String],
+    working_directory: &Path,
+) -> Result<Vec<String>, anyhow::Error> {
+    aliases::expand_aliases(args, working_directory)
+}
+
+/// Configure `shell` following `cargo`'s terminal settings—`--color`, `-v`/`-vv`, `-q` and
+/// the `term.*` configuration keys (e.g. `CARGO_TERM_COLOR`).
+pub fn configure_shell(
+    shell: &mut Shell,
+    args: &[String],
+    working_directory: &Path,
+) -> Result<(), anyhow::Error> {
+    terminal::configure_shell(shell, args, working_directory)
+}
+
+/// Check if the `cargo` command in `args` might be affected by code generation—i.e. if
+/// code generation must be performed before forwarding it to `cargo`.
+pub fn requires_codegen(args: &[String], working_directory: &Path) -> bool {
+    commands::requires_codegen(args, working_directory)
+}
+
 /// Find all codegen units in the current workspace and perform code generation for each of them,
 /// in an order that takes into account their respective dependency relationships.
-#[tracing::instrument(level = tracing::Level::DEBUG, name = "Generate crates", skip(cargo_path))]
+#[tracing::instrument(level = tracing::Level::DEBUG, name = "Generate crates", skip(cargo))]
 pub fn codegen(
-    cargo_path: &str,
+    cargo: &Cargo,
     working_directory: &Path,
     args: &[String],
+    message_format: MessageFormat,
     shell: &mut Shell,
 ) -> Result<(), Vec<anyhow::Error>> {
-    let package_graph = package_graph(cargo_path, shell).map_err(|e| vec![e])?;
-    let codegen_plan = compute_filtered_codegen_plan(working_directory, args, &package_graph)?;
-
-    let workspace_dir = package_graph
-        .workspace()
-        .root()
-        .canonicalize()
-        .context("Failed to get the canonical path to the root directory of this workspace")
-        .map_err(|e| vec![e])?;
-    for unit in codegen_plan {
-        generate_crate(&unit, cargo_path, &workspace_dir, shell).map_err(|e| vec![e])?;
+    let manifest_path = targets::extract_manifest_path(args, working_directory);
+    let metadata =
+        workspace_metadata(cargo, manifest_path.as_deref(), shell).map_err(|e| vec![e])?;
+    let package_graph = &metadata.package_graph;
+    let codegen_plan = compute_filtered_codegen_plan(
+        working_directory,
+        args,
+        &metadata,
+        metadata.default_members.as_deref(),
+        shell,
+    )?
+    .units;
+    let ctx = RunContext::new(cargo, package_graph, message_format).map_err(|e| vec![e])?;
+    emit_plan(&codegen_plan, &ctx, shell);
+
+    // Skip `px <sub_command>`
+    let json_messages = diagnostics::uses_json_messages(args.get(2..).unwrap_or_default());
+
+    let mut progress = Progress::new("Generating", codegen_plan.len(), shell);
+    let mut summary = Summary::default();
+    let mut outcome = Ok(());
+    for (i, unit) in codegen_plan.iter().enumerate() {
+        let name = unit.package_metadata.name();
+        // We stop at the first failure: the units that depend on it can't be generated.
+        if outcome.is_err() {
+            summary.push(UnitReport::new(name, UnitOutcome::Skipped));
+            continue;
+        }
+        progress.tick(i, name, shell);
+        let mut report = UnitReport::new(name, UnitOutcome::Generated);
+        if let Err(e) = generate_crate(unit, &ctx, &mut report, &mut progress, shell) {
+            report.outcome = UnitOutcome::Failed;
+            // Tools consuming `cargo`'s JSON messages wouldn't see the failure otherwise.
+            if json_messages {
+                diagnostics::emit_codegen_failure(shell, &unit.package_metadata, &e);
+            }
+            outcome = Err(vec![e]);
+        }
+        summary.push(report);
     }
+    progress.clear(shell);
+    let _ = shell.verbose(|shell| {
+        summary.print(shell);
+        Ok(())
+    });
 
-    Ok(())
+    outcome
 }
 
 /// Find all codegen units in the current workspace and verify that the associated projects
 /// are fresh—i.e. they don't need to be regenerated.
-#[tracing::instrument(level = tracing::Level::DEBUG, name = "Verify freshness", skip(cargo_path))]
+#[tracing::instrument(level = tracing::Level::DEBUG, name = "Verify freshness", skip(cargo))]
 pub fn verify(
-    cargo_path: &str,
+    cargo: &Cargo,
     working_directory: &Path,
     args: &[String],
+    message_format: MessageFormat,
     shell: &mut Shell,
 ) -> Result<(), Vec<anyhow::Error>> {
-    let package_graph = package_graph(cargo_path, shell).map_err(|e| vec![e])?;
-    let codegen_plan = compute_filtered_codegen_plan(working_directory, args, &package_graph)?;
-
-    let workspace_dir = package_graph
-        .workspace()
-        .root()
-        .canonicalize()
-        .context("Failed to get the canonical path to the root directory of this workspace")
-        .map_err(|e| vec![e])?;
-    for unit in codegen_plan {
+    let manifest_path = targets::extract_manifest_path(args, working_directory);
+    let metadata =
+        workspace_metadata(cargo, manifest_path.as_deref(), shell).map_err(|e| vec![e])?;
+    let package_graph = &metadata.package_graph;
+    // A freshness check that silently skips some codegen units is worse than no check at all:
+    // from the workspace root, we check every unit, even if default members are defined.
+    let codegen_plan =
+        compute_filtered_codegen_plan(working_directory, args, &metadata, None, shell)?.units;
+    let ctx = RunContext::new(cargo, package_graph, message_format).map_err(|e| vec![e])?;
+    emit_plan(&codegen_plan, &ctx, shell);
+
+    let mut progress = Progress::new("Verifying", codegen_plan.len(), shell);
+    for (i, unit) in codegen_plan.iter().enumerate() {
+        progress.tick(i, unit.package_metadata.name(), shell);
         let Some(verifier) = &unit.verifier else {
             return Err(vec![anyhow::anyhow!(
                 "`{}` doesn't define a verifier, therefore we can't verify if it's fresh",
                 unit.package_metadata.name()
             )]);
         };
-        verify_crate(
-            verifier,
-            &unit.package_metadata,
-            cargo_path,
-            &workspace_dir,
-            shell,
-        )
-        .map_err(|e| vec![e])?;
+        verify_crate(verifier, &unit.package_metadata, &ctx, &mut progress, shell)
+            .map_err(|e| vec![e])?;
     }
+    progress.clear(shell);
 
     Ok(())
 }
 
+/// Print the codegen plan for a `cargo` command (`px plan [<command>] [<args>...]`) to stdout,
+/// without performing code generation.
+///
+/// For each codegen unit, in execution order, we print its generator and verifier, the target
+/// packages that caused it to be included and the other codegen units it depends on.
+/// The command defaults to `build` if it's omitted.
+#[tracing::instrument(level = tracing::Level::DEBUG, name = "Print codegen plan", skip(cargo))]
+pub fn plan(
+    cargo: &Cargo,
+    working_directory: &Path,
+    args: &[String],
+    shell: &mut Shell,
+) -> Result<(), Vec<anyhow::Error>> {
+    // `px plan <command> [...]` is analysed as if it were `px <command> [...]`.
+    let mut command_args = vec!["px".to_owned()];
+    match args.get(2) {
+        Some(command) if !command.starts_with('-') => {}
+        _ => command_args.push("build".to_owned()),
+    }
+    command_args.extend(args.iter().skip(2).cloned());
+    let args = aliases::expand_aliases(&command_args, working_directory).unwrap_or(command_args);
+
+    let manifest_path = targets::extract_manifest_path(&args, working_directory);
+    let metadata =
+        workspace_metadata(cargo, manifest_path.as_deref(), shell).map_err(|e| vec![e])?;
+    let package_graph = &metadata.package_graph;
+    let FilteredPlan {
+        units: codegen_plan,
+        targets,
+        graph,
+        features,
+        include_dev,
+        platforms,
+    } = compute_filtered_codegen_plan(
+        working_directory,
+        &args,
+        &metadata,
+        metadata.default_members.as_deref(),
+        shell,
+    )?;
+    if codegen_plan.is_empty() {
+        let _ = shell.note(format!(
+            "there is nothing to generate for `cargo {}`",
+            args[1..].join(" ")
+        ));
+        return Ok(());
+    }
+
+    let name = |id: &PackageId| {
+        package_graph
+            .metadata(id)
+            .map_or_else(|_| id.repr().to_owned(), |p| p.name().to_owned())
+    };
+    let list = |names: Vec<String>| {
+        if names.is_empty() {
+            "none".to_owned()
+        } else {
+            names
+                .iter()
+                .map(|name| format!("`{name}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    };
+    let describe = |invocation: &BinaryInvocation| {
+        let mut description = format!(
+            "`{}` from `{}`",
+            invocation.binary.name,
+            invocation.binary.package_metadata.name()
+        );
+        if !invocation.args.is_empty() {
+            description.push_str(&format!(", with arguments {:?}", invocation.args));
+        }
+        description
+    };
+
+    // The codegen units required by each target package, according to `cargo`'s resolution.
+    let required: Vec<(&PackageId, HashSet<PackageId>)> = targets
+        .iter()
+        .flatten()
+        .map(|target_id| {
+            let built = resolve::packages_to_build(
+                package_graph,
+                std::slice::from_ref(target_id),
+                &features,
+                include_dev,
+                &platforms,
+            )
+            .unwrap_or_else(|e| {
+                tracing::debug!(
+                    error = ?e,
+                    "Failed to simulate `cargo`'s feature resolution, falling back to the full dependency graph"
+                );
+                let mut depends_cache = package_graph.new_depends_cache();
+                codegen_plan
+                    .iter()
+                    .map(|unit| unit.package_metadata.id())
+                    .filter(|unit_id| {
+                        *unit_id == target_id
+                            || depends_cache
+                                .depends_on(target_id, unit_id)
+                                .unwrap_or(false)
+                    })
+                    .cloned()
+                    .collect()
+            });
+            (target_id, built)
+        })
+        .collect();
+    let mut output = String::new();
+    for (i, unit) in codegen_plan.iter().enumerate() {
+        let package_id = unit.package_metadata.id();
+        let required_by = match &targets {
+            Some(_) => list(
+                required
+                    .iter()
+                    .filter(|(_, built)| built.contains(package_id))
+                    .map(|(target_id, _)| name(target_id))
+                    .collect(),
+            ),
+            None => "every workspace member".to_owned(),
+        };
+        // The units generating the dependencies of this unit, or of its generator.
+        let unit_dependencies = graph.unit_dependencies(unit);
+        let dependencies = list(
+            codegen_plan
+                .iter()
+                .map(|other| other.package_metadata.id())
+                .filter(|other| unit_dependencies.contains(other))
+                .map(name)
+                .collect(),
+        );
+        let verifier = unit
+            .verifier
+            .as_ref()
+            .map_or_else(|| "none".to_owned(), describe);
+        output.push_str(&format!(
+            "{}. `{}`\n   generator:   {}\n   verifier:    {verifier}\n   required by: {required_by}\n   depends on:  {dependencies}\n",
+            i + 1,
+            unit.package_metadata.name(),
+            describe(&unit.generator),
+        ));
+    }
+    write!(shell.out(), "{output}")
+        .context("Failed to print the codegen plan")
+        .map_err(|e| vec![e])
+}
+
+/// The state shared by all the steps of a `cargo px` run.
+struct RunContext<'a> {
+    cargo: &'a Cargo,
+    /// The canonical path to the root directory of the workspace.
+    workspace_path: PathBuf,
+    /// Where the output of generators and verifiers is saved.
+    logs_dir: PathBuf,
+    /// Where the fingerprints of the inputs of generators are saved.
+    fingerprints_dir: PathBuf,
+    message_format: MessageFormat,
+}
+
+impl<'a> RunContext<'a> {
+    fn new(
+        cargo: &'a Cargo,
+        package_graph: &PackageGraph,
+        message_format: MessageFormat,
+    ) -> Result<Self, anyhow::Error> {
+        let workspace = package_graph.workspace();
+        let workspace_path = workspace
+            .root()
+            .canonicalize()
+            .context("Failed to get the canonical path to the root directory of this workspace")?;
+        let target_dir = workspace.target_directory().as_std_path().to_owned();
+        Ok(Self {
+            cargo,
+            workspace_path,
+            logs_dir: target_dir.join("px").join("logs"),
+            fingerprints_dir: target_dir.join("px").join("fingerprints"),
+            message_format,
+        })
+    }
+
+    fn emit(&self, shell: &mut Shell, event: &Event) {
+        events::emit(shell, self.message_format, event);
+    }
+}
+
+fn emit_plan(codegen_plan: &[CodegenUnit], ctx: &RunContext, shell: &mut Shell) {
+    let units = codegen_plan
+        .iter()
+        .map(|unit| PlannedUnit {
+            package: unit.package_metadata.name(),
+            generator: &unit.generator.binary.name,
+            verifier: unit.verifier.as_ref().map(|v| v.binary.name.as_str()),
+        })
+        .collect();
+    ctx.emit(shell, &Event::PlanComputed { units });
+}
+
+/// The codegen plan for a `cargo` command.
+struct FilteredPlan<'a> {
+    /// The codegen units that the target packages depend on, in execution order.
+    units: Vec<CodegenUnit<'a>>,
+    /// The target packages of the command—`None` if they couldn't be narrowed down.
+    targets: Option<Vec<PackageId>>,
+    /// The graph the plan was computed from.
+    graph: AugmentedPackageGraph<'a>,
+    /// The inputs to `cargo`'s resolution, used to determine which units each target requires.
+    features: FeatureSelection,
+    include_dev: bool,
+    platforms: TargetPlatforms,
+}
+
 fn compute_filtered_codegen_plan<'a>(
     working_directory: &Path,
     args: &[String],
-    package_graph: &'a PackageGraph,
-) -> Result<Vec<CodegenUnit<'a>>, Vec<anyhow::Error>> {
+    metadata: &'a WorkspaceMetadata,
+    default_members: Option<&[PackageId]>,
+    shell: &mut Shell,
+) -> Result<FilteredPlan<'a>, Vec<anyhow::Error>> {
+    let package_graph = &metadata.package_graph;
     let mut codegen_units = extract_codegen_units(package_graph)?;
 
     if tracing::event_enabled!(tracing::Level::DEBUG) {
@@ -97,11 +401,18 @@ fn compute_filtered_codegen_plan<'a>(
         );
     }
 
-    let targets = determine_targets(args, working_directory, package_graph);
+    let targets = determine_targets(
+        args,
+        working_directory,
+        package_graph,
+        default_members,
+        shell,
+    );
 
     if tracing::event_enabled!(tracing::Level::DEBUG) {
         let target_names: Vec<_> = targets
             .iter()
+            .flatten()
             .map(|id| {
                 package_graph
                     .metadata(id)
@@ -116,17 +427,39 @@ fn compute_filtered_codegen_plan<'a>(
         );
     }
 
-    // Keep only the codegen units that appear in the dependency graph of the targets we've chosen
-    if !targets.is_empty() {
-        let mut depends_cache = package_graph.new_depends_cache();
-        codegen_units.retain(|unit| {
-            targets.iter().any(|target_id| {
-                unit.package_metadata.id() == target_id
-                    || depends_cache
-                        .depends_on(target_id, unit.package_metadata.id())
-                        .unwrap_or(false)
-            })
-        });
+    // Tests, examples and benchmarks can use the dev-dependencies of the target packages.
+    let include_dev = targets::needs_dev_dependencies(args);
+    // Skip `px <sub_command>`
+    let platforms = TargetPlatforms::new(&args[2..], &CargoConfig::load(working_directory));
+    tracing::debug!(
+        ?platforms,
+        "Determined the target platforms for this invocation"
+    );
+
+    // Skip `px <sub_command>`
+    let features = FeatureSelection::from_args(&args[2..]);
+    // Keep only the codegen units that are going to be built, given the targets we've chosen
+    // and the features that have been enabled.
+    if let Some(targets) = &targets {
+        match resolve::packages_to_build(package_graph, targets, &features, include_dev, &platforms)
+        {
+            Ok(built) => codegen_units.retain(|unit| built.contains(unit.package_metadata.id())),
+            Err(e) => {
+                tracing::debug!(
+                    error = ?e,
+                    "Failed to simulate `cargo`'s feature resolution, falling back to the full dependency graph"
+                );
+                let mut depends_cache = package_graph.new_depends_cache();
+                codegen_units.retain(|unit| {
+                    targets.iter().any(|target_id| {
+                        unit.package_metadata.id() == target_id
+                            || depends_cache
+                                .depends_on(target_id, unit.package_metadata.id())
+                                .unwrap_or(false)
+                    })
+                });
+            }
+        }
     }
 
     if tracing::event_enabled!(tracing::Level::DEBUG) {
@@ -140,18 +473,32 @@ fn compute_filtered_codegen_plan<'a>(
         );
     }
 
-    codegen_plan::codegen_plan(codegen_units, package_graph)
+    let dev_targets = match (include_dev, &targets) {
+        (false, _) => vec![],
+        (true, Some(targets)) => targets.clone(),
+        (true, None) => package_graph.workspace().member_ids().cloned().collect(),
+    };
+    let graph = AugmentedPackageGraph::new(codegen_units, package_graph, &dev_targets, &platforms)?;
+    Ok(FilteredPlan {
+        units: graph.codegen_plan(),
+        targets,
+        graph,
+        features,
+        include_dev,
+        platforms,
+    })
 }
 
 #[tracing::instrument(name = "Verify crate", skip_all, fields(crate_name = %package_metadata.name()))]
 fn verify_crate(
     verifier: &BinaryInvocation,
     package_metadata: &PackageMetadata,
-    cargo_path: &str,
-    workspace_path: &Path,
+    ctx: &RunContext,
+    progress: &mut Progress,
     shell: &mut Shell,
 ) -> Result<(), anyhow::Error> {
     let be_quiet = shell.verbosity() == Verbosity::Quiet;
+    let package_name = package_metadata.name();
 
     // Compile verifier
     {
@@ -160,24 +507,47 @@ fn verify_crate(
             "Compiling",
             format!(
                 "`{}`, the verifier for `{}`",
-                verifier.binary.name,
-                package_metadata.name()
+                verifier.binary.name, package_name
             ),
         );
-        let mut cmd = verifier.build_command(cargo_path, be_quiet);
-        cmd.env("CARGO_PX_WORKSPACE_ROOT_DIR", workspace_path)
-            .stdout(std::process::Stdio::inherit())
+        progress.draw(shell);
+        ctx.emit(
+            shell,
+            &Event::CompileStarted {
+                package: package_name,
+                binary: &verifier.binary.name,
+                role: Role::Verifier,
+            },
+        );
+        let mut cmd = verifier.build_command(ctx.cargo, be_quiet);
+        cmd.env("CARGO_PX_WORKSPACE_ROOT_DIR", &ctx.workspace_path)
+            .env("CARGO_TERM_COLOR", terminal::color_env(shell))
+            // Our stdout is reserved for machine-readable output.
+            .stdout(std::io::stderr())
             .stderr(std::process::Stdio::inherit());
 
         let err_msg = || {
             format!(
                 "Failed to compile `{}`, the verifier for `{}`",
-                verifier.binary.name,
-                package_metadata.name()
+                verifier.binary.name, package_name
             )
         };
 
-        let status = cmd.status().with_context(err_msg)?;
+        let _ = shell.verbose(|shell| shell.status("Running", process::display_command(&cmd)));
+        // The build output is not captured: the terminal is handed over to `cargo`.
+        progress.clear(shell);
+        let status = process::run(&mut cmd, None);
+        ctx.emit(
+            shell,
+            &Event::CompileFinished {
+                package: package_name,
+                binary: &verifier.binary.name,
+                role: Role::Verifier,
+                success: matches!(&status, Ok(status) if status.success()),
+                duration: timer.elapsed().as_secs_f64(),
+            },
+        );
+        let status = status.with_context(err_msg)?;
         if !status.success() {
             anyhow::bail!(err_msg());
         }
@@ -186,46 +556,70 @@ fn verify_crate(
             format!(
                 "`{}`, the verifier for `{}`, in {:.3}s",
                 verifier.binary.name,
-                package_metadata.name(),
+                package_name,
                 timer.elapsed().as_secs_f32()
             ),
         );
+        progress.draw(shell);
     }
 
     // Invoke verifier
     {
         let timer = Instant::now();
-        let _ = shell.status("Verifying", format!("`{}`", package_metadata.name()));
-        let mut cmd = verifier.run_command(cargo_path, be_quiet);
+        let _ = shell.status("Verifying", format!("`{package_name}`"));
+        progress.draw(shell);
+        let mut cmd = verifier.run_command(ctx.cargo, be_quiet);
 
         cmd.env(
             "CARGO_PX_GENERATED_PKG_MANIFEST_PATH",
             package_metadata.manifest_path(),
         )
-        .env("CARGO_PX_WORKSPACE_ROOT_DIR", workspace_path)
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit());
+        .env("CARGO_PX_WORKSPACE_ROOT_DIR", &ctx.workspace_path)
+        .env("CARGO_TERM_COLOR", terminal::color_env(shell))
+        // Generators and verifiers are not interactive: they shouldn't block waiting for input.
+        .stdin(std::process::Stdio::null());
 
         let err_msg = || {
             format!(
                 "Failed to run `{}`, the verifier for `{}`",
-                verifier.binary.name,
-                package_metadata.name()
+                verifier.binary.name, package_name
             )
         };
 
-        let status = cmd.status().with_context(err_msg)?;
-        if !status.success() {
-            anyhow::bail!(err_msg());
+        let _ = shell.verbose(|shell| shell.status("Running", process::display_command(&cmd)));
+        let log_path = ctx.logs_dir.join(format!("{package_name}.verify.log"));
+        let captured = run_captured(&mut cmd, verifier.timeout, package_name, &log_path, shell);
+        ctx.emit(
+            shell,
+            &Event::VerificationFinished {
+                package: package_name,
+                success: matches!(&captured, Ok(captured) if captured.succeeded()),
+                duration: timer.elapsed().as_secs_f64(),
+            },
+        );
+        let captured = captured
+            .map_err(|e| anyhow::anyhow!(process::reproduction_hint(&cmd)).context(e.to_string()))
+            .with_context(err_msg)?;
+        if !captured.status.success() {
+            return Err(anyhow::anyhow!(process::reproduction_hint(&cmd))
+                .context(format!("The verifier exited with {}", captured.status))
+                .context(err_msg()));
+        }
+        let errors = captured.errors();
+        if errors > 0 {
+            return Err(anyhow::anyhow!(process::reproduction_hint(&cmd))
+                .context(format!("The verifier reported {errors} error(s)"))
+                .context(err_msg()));
         }
         let _ = shell.status(
             "Verified",
             format!(
                 "`{}` in {:.3}s",
-                package_metadata.name(),
+                package_name,
                 timer.elapsed().as_secs_f32()
             ),
         );
+        progress.draw(shell);
     }
     Ok(())
 }
@@ -233,37 +627,73 @@ fn verify_crate(
 #[tracing::instrument(name = "Generate crate", skip_all, fields(crate_name = %unit.package_metadata.name()))]
 fn generate_crate(
     unit: &codegen_unit::CodegenUnit,
-    cargo_path: &str,
-    workspace_path: &Path,
+    ctx: &RunContext,
+    report: &mut UnitReport,
+    progress: &mut Progress,
     shell: &mut Shell,
 ) -> Result<(), anyhow::Error> {
     let be_quiet = shell.verbosity() == Verbosity::Quiet;
+    let package_name = unit.package_metadata.name();
 
     // Compile generator
-    {
+    let executable = {
         let timer = Instant::now();
         let _ = shell.status(
             "Compiling",
             format!(
                 "`{}`, the code generator for `{}`",
-                unit.generator.binary.name,
-                unit.package_metadata.name()
+                unit.generator.binary.name, package_name
             ),
         );
-        let mut cmd = unit.generator.build_command(cargo_path, be_quiet);
-        cmd.env("CARGO_PX_WORKSPACE_ROOT_DIR", workspace_path)
-            .stdout(std::process::Stdio::inherit())
+        progress.draw(shell);
+        ctx.emit(
+            shell,
+            &Event::CompileStarted {
+                package: package_name,
+                binary: &unit.generator.binary.name,
+                role: Role::Generator,
+            },
+        );
+        let mut cmd = unit.generator.build_command(ctx.cargo, be_quiet);
+        // We learn where the executable is from the `compiler-artifact` record, while
+        // diagnostics are still rendered on stderr.
+        // It also keeps `cargo`'s output away from our stdout, which is reserved for
+        // machine-readable output.
+        cmd.arg("--message-format=json-render-diagnostics")
+            .env("CARGO_PX_WORKSPACE_ROOT_DIR", &ctx.workspace_path)
+            .env("CARGO_TERM_COLOR", terminal::color_env(shell))
             .stderr(std::process::Stdio::inherit());
 
         let err_msg = || {
             format!(
                 "Failed to compile `{}`, the code generator for `{}`",
-                unit.generator.binary.name,
-                unit.package_metadata.name()
+                unit.generator.binary.name, package_name
             )
         };
 
-        let status = cmd.status().with_context(err_msg)?;
+        let _ = shell.verbose(|shell| shell.status("Running", process::display_command(&cmd)));
+        // The build output is not captured: the terminal is handed over to `cargo`.
+        progress.clear(shell);
+        let mut executable = None;
+        let status = process::run_reading_stdout(&mut cmd, None, |message| {
+            if let Some(path) =
+                fingerprint::executable_from_message(message, &unit.generator.binary.name)
+            {
+                executable = Some(path);
+            }
+        });
+        report.build_time = Some(timer.elapsed());
+        ctx.emit(
+            shell,
+            &Event::CompileFinished {
+                package: package_name,
+                binary: &unit.generator.binary.name,
+                role: Role::Generator,
+                success: matches!(&status, Ok(status) if status.success()),
+                duration: timer.elapsed().as_secs_f64(),
+            },
+        );
+        let status = status.with_context(err_msg)?;
         if !status.success() {
             anyhow::bail!(err_msg());
         }
@@ -272,65 +702,299 @@ fn generate_crate(
             format!(
                 "`{}`, the code generator for `{}`, in {:.3}s",
                 unit.generator.binary.name,
-                unit.package_metadata.name(),
+                package_name,
                 timer.elapsed().as_secs_f32()
             ),
         );
-    }
+        progress.draw(shell);
+        executable
+    };
 
     // Invoke generator
     {
-        let timer = Instant::now();
-        let _ = shell.status("Generating", format!("`{}`", unit.package_metadata.name()));
-        let mut cmd = unit.generator.run_command(cargo_path, be_quiet);
+        let package_dir = unit
+            .package_metadata
+            .manifest_path()
+            .parent()
+            .map(|dir| dir.as_std_path().to_owned())
+            .unwrap_or_default();
+        let mut cmd = unit.generator.run_command(ctx.cargo, be_quiet);
 
         cmd.env(
             "CARGO_PX_GENERATED_PKG_MANIFEST_PATH",
             unit.package_metadata.manifest_path(),
         )
-        .env("CARGO_PX_WORKSPACE_ROOT_DIR", workspace_path)
-        .stdout(std::process::Stdio::inherit())
-        .stderr(std::process::Stdio::inherit());
+        .env("CARGO_PX_WORKSPACE_ROOT_DIR", &ctx.workspace_path)
+        .env("CARGO_TERM_COLOR", terminal::color_env(shell))
+        // Generators and verifiers are not interactive: they shouldn't block waiting for input.
+        .stdin(std::process::Stdio::null());
+
+        let fingerprint_path = ctx.fingerprints_dir.join(format!("{package_name}.json"));
+        let base_dir = working_directory(&cmd);
+        let fingerprint = Fingerprint::load(&fingerprint_path);
+        // Snapshots walk the whole package: we only take them if the summary or
+        // the fingerprint needs them.
+        let is_verbose = shell.verbosity() == Verbosity::Verbose;
+        let before = (is_verbose || fingerprint.is_some()).then(|| summary::snapshot(&package_dir));
+        if let (Some(executable), Some(fingerprint), Some(before)) =
+            (&executable, &fingerprint, &before)
+        {
+            if fingerprint.is_fresh(executable, &unit.generator.args, &base_dir, before) {
+                report.outcome = UnitOutcome::Fresh;
+                let _ = shell.status("Fresh", format!("`{package_name}`"));
+                progress.draw(shell);
+                ctx.emit(
+                    shell,
+                    &Event::GenerationFresh {
+                        package: package_name,
+                    },
+                );
+                return Ok(());
+            }
+        }
+        // The fingerprint is only valid if the generator runs to completion successfully.
+        let _ = std::fs::remove_file(&fingerprint_path);
 
+        let timer = Instant::now();
+        let _ = shell.status("Generating", format!("`{package_name}`"));
+        progress.draw(shell);
+        ctx.emit(
+            shell,
+            &Event::GenerationStarted {
+                package: package_name,
+            },
+        );
         let err_msg = || {
             format!(
                 "Failed to run `{}`, the code generator for package `{}`",
-                unit.generator.binary.name,
-                unit.package_metadata.name()
+                unit.generator.binary.name, package_name
             )
         };
 
-        let status = cmd.status().with_context(err_msg)?;
-        if !status.success() {
-            anyhow::bail!(err_msg());
+        let _ = shell.verbose(|shell| shell.status("Running", process::display_command(&cmd)));
+        let log_path = ctx.logs_dir.join(format!("{package_name}.log"));
+        let captured = run_captured(
+            &mut cmd,
+            unit.generator.timeout,
+            package_name,
+            &log_path,
+            shell,
+        );
+        report.run_time = Some(timer.elapsed());
+        let after = is_verbose.then(|| summary::snapshot(&package_dir));
+        if let (Some(before), Some(after)) = (&before, &after) {
+            report.changed_files = Some(summary::changed_files(before, after));
+        }
+        ctx.emit(
+            shell,
+            &Event::GenerationFinished {
+                package: package_name,
+                success: matches!(&captured, Ok(captured) if captured.succeeded()),
+                duration: timer.elapsed().as_secs_f64(),
+            },
+        );
+        let captured = captured
+            .map_err(|e| anyhow::anyhow!(process::reproduction_hint(&cmd)).context(e.to_string()))
+            .with_context(err_msg)?;
+        if !captured.status.success() {
+            return Err(anyhow::anyhow!(process::reproduction_hint(&cmd))
+                .context(format!(
+                    "The code generator exited with {}",
+                    captured.status
+                ))
+                .context(err_msg()));
+        }
+        let errors = captured.errors();
+        if errors > 0 {
+            return Err(anyhow::anyhow!(process::reproduction_hint(&cmd))
+                .context(format!("The code generator reported {errors} error(s)"))
+                .context(err_msg()));
+        }
+
+        let (mut files, mut env) = (Vec::new(), Vec::new());
+        for directive in captured.directives {
+            match directive {
+                Directive::RerunIfChanged(path) => files.push(base_dir.join(path)),
+                Directive::RerunIfEnvChanged(name) => env.push(name),
+                Directive::Warning(_) | Directive::Error(_) => {}
+            }
+        }
+        // We can't tell if the generator has been rebuilt without knowing where its executable is.
+        if let Some(executable) = executable.filter(|_| !files.is_empty() || !env.is_empty()) {
+            let after = after.unwrap_or_else(|| summary::snapshot(&package_dir));
+            let fingerprint = Fingerprint::new(
+                &executable,
+                &unit.generator.args,
+                &base_dir,
+                files,
+                env,
+                &after,
+            );
+            if let Some(Err(e)) = fingerprint.map(|f| f.save(&fingerprint_path)) {
+                tracing::debug!(error = ?e, "Failed to save the fingerprint for `{package_name}`");
+            }
         }
         let _ = shell.status(
             "Generated",
             format!(
                 "`{}` in {:.3}s",
-                unit.package_metadata.name(),
+                package_name,
                 timer.elapsed().as_secs_f32()
             ),
         );
+        progress.draw(shell);
     }
     Ok(())
 }
 
+/// Run a generator or a verifier for `unit_name`, capturing its output.
+///
+/// The output is saved to `log_path`. It's shown on stderr, prefixed with the unit name,
+/// as soon as it's produced if we're in verbose mode, or once the process is done if it failed.
+/// Directives (e.g. `cargo-px:warning=...`) are always rendered as soon as they're printed.
+fn run_captured(
+    cmd: &mut std::process::Command,
+    timeout: Option<Duration>,
+    unit_name: &str,
+    log_path: &Path,
+    shell: &mut Shell,
+) -> Result<Captured, anyhow::Error> {
+    let mut log_file = log_path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::File::create(log_path))
+        .map_err(|e| {
+            tracing::debug!(error = ?e, path = %log_path.display(), "Failed to create a log file");
+        })
+        .ok();
+    let base_dir = working_directory(cmd);
+    let is_verbose = shell.verbosity() == Verbosity::Verbose;
+    let prefix = format!("[{unit_name}]");
+    let mut lines = Vec::new();
+    let mut directives = Vec::new();
+    let outcome = process::run_captured(cmd, timeout, |stream, line| {
+        if let Some(log_file) = &mut log_file {
+            let _ = writeln!(log_file, "{line}");
+        }
+        if stream == process::Stream::Stdout {
+            match Directive::parse(line) {
+                Some(Ok(directive)) => {
+                    directive.render(unit_name, &base_dir, shell);
+                    directives.push(directive);
+                    return;
+                }
+                Some(Err(e)) => {
+                    let _ = shell.warn(format!("{unit_name}: {e}"));
+                    return;
+                }
+                None => {}
+            }
+        }
+        if is_verbose {
+            let _ = writeln!(shell.err(), "{prefix} {line}");
+        } else {
+            lines.push(line.to_owned());
+        }
+    });
+    let outcome = outcome.map(|status| Captured { status, directives });
+
+    let has_failed = !matches!(&outcome, Ok(captured) if captured.succeeded());
+    if has_failed {
+        for line in &lines {
+            let _ = writeln!(shell.err(), "{prefix} {line}");
+        }
+        if log_file.is_some() {
+            let _ = shell.note(format!(
+                "the output of `{unit_name}` has been saved to {}",
+                log_path.display()
+            ));
+        }
+    }
+    outcome
+}
+
+/// The directory `cmd` runs in. Relative paths in directives are resolved against it.
+fn working_directory(cmd: &std::process::Command) -> PathBuf {
+    cmd.get_current_dir()
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default()
+}
+
+/// The outcome of a generator or verifier run.
+struct Captured {
+    status: std::process::ExitStatus,
+    /// The directives printed by the process, in order.
+    directives: Vec<Directive>,
+}
+
+impl Captured {
+    /// How many `cargo-px:error` directives were printed.
+    fn errors(&self) -> usize {
+        self.directives
+            .iter()
+            .filter(|d| matches!(d, Directive::Error(_)))
+            .count()
+    }
+
+    fn succeeded(&self) -> bool {
+        self.status.success() && self.errors() == 0
+    }
+}
+
+/// The output of `cargo metadata` for the current workspace.
+struct WorkspaceMetadata {
+    package_graph: PackageGraph,
+    /// The packages that `cargo` operates on when invoked from the workspace root
+    /// without an explicit package selection—i.e. `workspace.default-members`.
+    ///
+    /// It's `None` if the `cargo` toolchain is too old to report them (<1.71).
+    default_members: Option<Vec<PackageId>>,
+}
+
 /// Build the package graph for the current workspace.
+///
+/// If `manifest_path` is provided, the workspace is discovered starting from it rather than
+/// from the current working directory.
 #[tracing::instrument(name = "Compute package graph", skip_all)]
-fn package_graph(cargo_path: &str, shell: &mut Shell) -> Result<PackageGraph, anyhow::Error> {
+fn workspace_metadata(
+    cargo: &Cargo,
+    manifest_path: Option<&Path>,
+    shell: &mut Shell,
+) -> Result<WorkspaceMetadata, anyhow::Error> {
     let timer = Instant::now();
     let _ = shell.status("Computing", "package graph");
-    let mut metadata_cmd = guppy::MetadataCommand::new();
-    metadata_cmd.cargo_path(cargo_path);
-    let package_graph = metadata_cmd
+    let mut metadata_cmd = cargo.metadata_command();
+    if let Some(manifest_path) = manifest_path {
+        metadata_cmd.manifest_path(manifest_path);
+    }
+    let _ = shell.verbose(|shell| {
+        shell.status(
+            "Running",
+            process::display_command(&metadata_cmd.cargo_command()),
+        )
+    });
+    let metadata = metadata_cmd
         .exec()
-        .context("Failed to execute `cargo metadata`")?
+        .context("Failed to execute `cargo metadata`")?;
+    // `guppy` doesn't expose the default members of the workspace, so we extract them
+    // from the raw output of `cargo metadata`.
+    let default_members = serde_json::to_value(&metadata)
+        .ok()
+        .and_then(|mut raw| {
+            serde_json::from_value::<Vec<String>>(raw.get_mut("workspace_default_members")?.take())
+                .ok()
+        })
+        .map(|ids| ids.into_iter().map(PackageId::new).collect());
+    let package_graph = metadata
         .build_graph()
-        .context("Failed to build a package graph starting from the output of `cargo metadata`");
+        .context("Failed to build a package graph starting from the output of `cargo metadata`")?;
     let _ = shell.status(
         "Computed",
         format!("package graph in {:.3}s", timer.elapsed().as_secs_f32()),
     );
-    package_graph
+    Ok(WorkspaceMetadata {
+        package_graph,
+        default_members,
+    })
 }
@@ -0,0 +1,218 @@
+//! Parsing and matching of the package specifications accepted by `cargo`'s `-p`/`--package` flag.
+//!
+//! `cargo` accepts both [package ID specifications](https://doc.rust-lang.org/cargo/reference/pkgid-spec.html)
+//! (e.g. `foo`, `foo@1.2.0`, `path+file:///path/to/foo#foo@1.2.0`) and glob patterns
+//! matched against package names (e.g. `api-*`).
+use std::path::Path;
+
+use anyhow::Context;
+use guppy::{graph::PackageMetadata, Version};
+
+/// A package specification, as passed to `-p`/`--package`.
+#[derive(Debug)]
+pub(crate) enum PackageSpec {
+    /// A glob pattern, matched against the name of each package.
+    Glob(glob::Pattern),
+    /// A package ID specification.
+    Id(PackageIdSpec),
+}
+
+/// A package ID specification.
+///
+/// Every component is optional: a package matches the spec if it matches all the
+/// components that have been specified.
+#[derive(Debug)]
+pub(crate) struct PackageIdSpec {
+    name: Option<String>,
+    version: Option<PartialVersion>,
+    url: Option<url::Url>,
+}
+
+/// A (possibly incomplete) version, e.g. `1`, `1.2` or `1.2.0-beta.1`.
+#[derive(Debug)]
+enum PartialVersion {
+    Full(Version),
+    Major(u64),
+    MajorMinor(u64, u64),
+}
+
+impl PackageSpec {
+    /// Parse a package specification, using the same rules as `cargo`.
+    pub(crate) fn parse(spec: &str) -> Result<Self, anyhow::Error> {
+        if spec.contains("://") {
+            return PackageIdSpec::parse_url(spec).map(PackageSpec::Id);
+        }
+        if spec.contains(['*', '?', '[', ']']) {
+            let pattern = glob::Pattern::new(spec)
+                .with_context(|| format!("`{spec}` is not a valid glob pattern"))?;
+            return Ok(PackageSpec::Glob(pattern));
+        }
+        let (name, version) = split_name_and_version(spec)?;
+        Ok(PackageSpec::Id(PackageIdSpec {
+            name: Some(name.to_owned()),
+            version,
+            url: None,
+        }))
+    }
+
+    /// Check if the package matches this specification.
+    pub(crate) fn matches(&self, package: &PackageMetadata) -> bool {
+        match self {
+            PackageSpec::Glob(pattern) => pattern.matches(package.name()),
+            PackageSpec::Id(spec) => spec.matches(package),
+        }
+    }
+}
+
+impl PackageIdSpec {
+    /// Parse a URL-based package ID specification, e.g. `path+file:///path/to/foo#foo@1.2.0`.
+    fn parse_url(spec: &str) -> Result<Self, anyhow::Error> {
+        let (url, fragment) = match spec.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (spec, None),
+        };
+        let url = url::Url::parse(url)
+            .with_context(|| format!("`{spec}` is not a valid package ID specification"))?;
+        // If the fragment doesn't specify it, the name of the package is inferred
+        // from the last segment of the URL path.
+        let inferred_name = || {
+            url.path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .filter(|segment| !segment.is_empty())
+                .map(ToOwned::to_owned)
+        };
+        let (name, version) = match fragment {
+            None => (inferred_name(), None),
+            Some(fragment) => match PartialVersion::parse(fragment) {
+                Ok(version) => (inferred_name(), Some(version)),
+                Err(_) => {
+                    let (name, version) = split_name_and_version(fragment)?;
+                    (Some(name.to_owned()), version)
+                }
+            },
+        };
+        Ok(PackageIdSpec {
+            name,
+            version,
+            url: Some(url),
+        })
+    }
+
+    fn matches(&self, package: &PackageMetadata) -> bool {
+        if let Some(name) = &self.name {
+            if name != package.name() {
+                return false;
+            }
+        }
+        if let Some(version) = &self.version {
+            if !version.matches(package.version()) {
+                return false;
+            }
+        }
+        if let Some(url) = &self.url {
+            let Some(package_dir) = package.manifest_path().parent() else {
+                return false;
+            };
+            if !url_points_to(url, package_dir.as_std_path()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl PartialVersion {
+    fn parse(version: &str) -> Result<Self, anyhow::Error> {
+        if let Ok(version) = Version::parse(version) {
+            return Ok(PartialVersion::Full(version));
+        }
+        let invalid = || anyhow::anyhow!("`{version}` is not a valid version");
+        let mut components = version.split('.');
+        let major = components
+            .next()
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(invalid)?;
+        let Some(minor) = components.next() else {
+            return Ok(PartialVersion::Major(major));
+        };
+        let minor = minor.parse().map_err(|_| invalid())?;
+        if components.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(PartialVersion::MajorMinor(major, minor))
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        match self {
+            PartialVersion::Full(v) => v == version,
+            PartialVersion::Major(major) => *major == version.major,
+            PartialVersion::MajorMinor(major, minor) => {
+                *major == version.major && *minor == version.minor
+            }
+        }
+    }
+}
+
+/// Split `name@version` (or the legacy `name:version`) into its components.
+fn split_name_and_version(spec: &str) -> Result<(&str, Option<PartialVersion>), anyhow::Error> {
+    match spec.split_once(['@', ':']) {
+        Some((name, version)) => Ok((name, Some(PartialVersion::parse(version)?))),
+        None => Ok((spec, None)),
+    }
+}
+
+/// Check if `url` is a `file://` (or `path+file://`) URL pointing at `dir`.
+///
+/// Workspace members are always local packages, therefore URLs with any other scheme
+/// (e.g. `registry+https://` or `git+https://`) never match.
+fn url_points_to(url: &url::Url, dir: &Path) -> bool {
+    let is_local = matches!(url.scheme(), "file" | "path+file");
+    if !is_local {
+        return false;
+    }
+    let Ok(path) = url.to_file_path() else {
+        return false;
+    };
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_owned());
+    canonical(&path) == canonical(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_and_version() {
+        let PackageSpec::Id(spec) = PackageSpec::parse("foo@1.2").unwrap() else {
+            panic!("Expected a package ID spec")
+        };
+        assert_eq!(spec.name.as_deref(), Some("foo"));
+        assert!(spec.version.unwrap().matches(&Version::new(1, 2, 7)));
+    }
+
+    #[test]
+    fn parses_globs() {
+        let PackageSpec::Glob(pattern) = PackageSpec::parse("api-*").unwrap() else {
+            panic!("Expected a glob pattern")
+        };
+        assert!(pattern.matches("api-client"));
+        assert!(!pattern.matches("server"));
+    }
+
+    #[test]
+    fn infers_name_from_url() {
+        let PackageSpec::Id(spec) = PackageSpec::parse("path+file:///path/to/foo#1.2.0").unwrap()
+        else {
+            panic!("Expected a package ID spec")
+        };
+        assert_eq!(spec.name.as_deref(), Some("foo"));
+        assert!(spec.version.unwrap().matches(&Version::new(1, 2, 0)));
+
+        let PackageSpec::Id(spec) =
+            PackageSpec::parse("path+file:///path/to/foo#bar@1.2.0").unwrap()
+        else {
+            panic!("Expected a package ID spec")
+        };
+        assert_eq!(spec.name.as_deref(), Some("bar"));
+    }
+}
@@ -0,0 +1,576 @@
+//! Run the child processes spawned by `cargo px` (e.g. code generators), making sure
+//! that they are shut down cleanly if `cargo px` is interrupted or if they take too long.
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The error returned when `cargo px` receives a termination signal while waiting
+/// for a child process.
+#[derive(Debug)]
+pub(crate) struct Interrupted {
+    signal: i32,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.signal {
+            imp::SIGINT => write!(f, "Interrupted by the user (SIGINT)"),
+            imp::SIGTERM => write!(f, "Interrupted by a termination request (SIGTERM)"),
+            imp::SIGHUP => write!(f, "Interrupted because the terminal was closed (SIGHUP)"),
+            signal => write!(f, "Interrupted by signal {signal}"),
+        }
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// The error returned when a child process doesn't complete within its timeout.
+#[derive(Debug)]
+pub(crate) struct TimedOut {
+    elapsed: Duration,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Timed out after {:.3}s, the process was killed",
+            self.elapsed.as_secs_f32()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The output stream a line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Run `cmd` to completion and return its exit status.
+///
+/// The child stays in our process group—it shares the terminal with us, e.g. to print
+/// `cargo`'s build progress.
+/// If `cargo px` receives SIGINT, SIGTERM or SIGHUP, the signal is forwarded to the child;
+/// if it's still running after a grace period, it's killed.
+/// Either way, an [`Interrupted`] error is returned.
+///
+/// If `timeout` elapses before the child exits, it's shut down in the same way and
+/// a [`TimedOut`] error is returned.
+pub(crate) fn run(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<ExitStatus, anyhow::Error> {
+    let child = imp::spawn(cmd, false)?;
+    wait(child, false, timeout, std::thread::sleep)
+}
+
+/// Run `cmd` to completion, like [`run`], capturing its output.
+///
+/// `on_line` is invoked, on the current thread, for every line the child writes
+/// to stdout or stderr, as soon as it's available.
+///
+/// Since it doesn't need the terminal, the child is spawned in its own process group on Unix
+/// (or job object on Windows), so that the whole process tree (e.g. `cargo run` and the code
+/// generator it invokes) can be shut down as a unit.
+/// On Unix, suspending `cargo px` (e.g. with Ctrl-Z) suspends the group too.
+pub(crate) fn run_captured(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+    on_line: impl FnMut(Stream, &str),
+) -> Result<ExitStatus, anyhow::Error> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    run_piped(cmd, true, timeout, on_line)
+}
+
+/// Run `cmd` to completion, like [`run`], reading its stdout.
+///
+/// `on_line` is invoked, on the current thread, for every line the child writes to stdout.
+/// Stderr is left as configured on `cmd`—e.g. inherited, to show `cargo`'s progress.
+pub(crate) fn run_reading_stdout(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+    mut on_line: impl FnMut(&str),
+) -> Result<ExitStatus, anyhow::Error> {
+    cmd.stdout(Stdio::piped());
+    run_piped(cmd, false, timeout, |_, line| on_line(line))
+}
+
+/// Run `cmd` to completion, invoking `on_line` for every line written to its piped streams.
+fn run_piped(
+    cmd: &mut Command,
+    own_group: bool,
+    timeout: Option<Duration>,
+    mut on_line: impl FnMut(Stream, &str),
+) -> Result<ExitStatus, anyhow::Error> {
+    let mut child = imp::spawn(cmd, own_group)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let pipes: [(Stream, Option<Box<dyn Read + Send>>); 2] = [
+        (
+            Stream::Stdout,
+            child
+                .stdout
+                .take()
+                .map(|p| Box::new(p) as Box<dyn Read + Send>),
+        ),
+        (
+            Stream::Stderr,
+            child
+                .stderr
+                .take()
+                .map(|p| Box::new(p) as Box<dyn Read + Send>),
+        ),
+    ];
+    for (stream, pipe) in pipes {
+        let Some(pipe) = pipe else { continue };
+        let sender = sender.clone();
+        std::thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            while let Ok(n) = reader.read_until(b'\n', &mut line) {
+                if n == 0 {
+                    break;
+                }
+                let text = String::from_utf8_lossy(&line);
+                let text = text.trim_end_matches(['\n', '\r']).to_owned();
+                if sender.send((stream, text)).is_err() {
+                    break;
+                }
+                line.clear();
+            }
+        });
+    }
+    // The channel is closed when all reader threads are done.
+    drop(sender);
+
+    let outcome = wait(child, own_group, timeout, |interval| {
+        let deadline = Instant::now() + interval;
+        loop {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((stream, line)) => on_line(stream, &line),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                // The child closed its pipes, but it's still running: `recv_timeout` would
+                // return immediately from now on, so we must sleep instead.
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    break;
+                }
+            }
+        }
+    });
+    // Flush whatever is left in the pipes.
+    // The child may have spawned background processes that inherited the pipes and are still
+    // holding them open: we stop waiting for them once they've gone quiet for a while.
+    while let Ok((stream, line)) = receiver.recv_timeout(DRAIN_TIMEOUT) {
+        on_line(stream, &line);
+    }
+    outcome
+}
+
+/// How long we wait for the next line of output of a child process after it has exited.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Wait for `child` to exit, shutting it down if we're interrupted or if it takes too long.
+///
+/// `own_group` must be set if `child` was spawned in its own process group.
+/// `idle` is invoked between checks, with the amount of time it should block for.
+fn wait(
+    mut child: Child,
+    own_group: bool,
+    timeout: Option<Duration>,
+    idle: impl FnMut(Duration),
+) -> Result<ExitStatus, anyhow::Error> {
+    let outcome = wait_for_exit(&mut child, own_group, timeout, idle);
+    imp::release(&child);
+    outcome
+}
+
+fn wait_for_exit(
+    child: &mut Child,
+    own_group: bool,
+    timeout: Option<Duration>,
+    mut idle: impl FnMut(Duration),
+) -> Result<ExitStatus, anyhow::Error> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if let Some(signal) = interrupted() {
+            tracing::debug!(signal, "Forwarding the signal to the child process");
+            imp::shut_down(child, signal, own_group)?;
+            return Err(Interrupted { signal }.into());
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            tracing::debug!("The child process timed out, shutting it down");
+            imp::shut_down(child, imp::SIGTERM, own_group)?;
+            return Err(TimedOut {
+                elapsed: start.elapsed(),
+            }
+            .into());
+        }
+        idle(POLL_INTERVAL);
+    }
+}
+
+/// Render `cmd` in the style of `cargo`'s verbose output: the `CARGO_PX_*` environment
+/// variables, followed by the program and its arguments, quoted for the shell.
+/// The working directory is appended at the end.
+pub(crate) fn display_command(cmd: &Command) -> String {
+    let working_directory = cmd
+        .get_current_dir()
+        .map(ToOwned::to_owned)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    format!(
+        "`{}` in `{}`",
+        command_line(cmd),
+        working_directory.display()
+    )
+}
+
+/// The command line that `cmd` will execute, including the `CARGO_PX_*` environment variables
+/// it sets. It can be copy-pasted in a POSIX shell.
+pub(crate) fn command_line(cmd: &Command) -> String {
+    let env = cmd.get_envs().filter_map(|(key, value)| {
+        let key = key.to_string_lossy();
+        let value = value?;
+        key.starts_with("CARGO_PX_")
+            .then(|| format!("{key}={}", shell_quote(&value.to_string_lossy())))
+    });
+    let program = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()));
+    env.chain(program).collect::<Vec<_>>().join(" ")
+}
+
+/// A message explaining how to reproduce the execution of `cmd` outside of `cargo px`:
+/// same working directory, same `CARGO_PX_*` environment variables, same arguments.
+pub(crate) fn reproduction_hint(cmd: &Command) -> String {
+    let working_directory = cmd
+        .get_current_dir()
+        .map(ToOwned::to_owned)
+        .or_else(|| std::env::current_dir().ok())
+        .unwrap_or_default();
+    format!(
+        "You can reproduce the failure by running:\n\ncd {} && {}",
+        shell_quote(&working_directory.to_string_lossy()),
+        command_line(cmd)
+    )
+}
+
+/// Quote `arg` for a POSIX shell, if it contains any character that the shell would interpret.
+fn shell_quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,@+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// The signal that interrupted `cargo px`, if any.
+pub fn interrupted() -> Option<i32> {
+    imp::interrupted()
+}
+
+/// Terminate `cargo px` because it was interrupted by `signal`.
+///
+/// On Unix, we restore the default disposition of `signal` and raise it again: our parent
+/// (e.g. a shell) sees that we were killed by the signal, exactly as it would for `cargo`.
+pub fn exit_with_signal(signal: i32) -> ! {
+    imp::raise(signal);
+    // The signal didn't terminate us: fall back to the shell convention.
+    std::process::exit(128 + signal)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
+    use std::time::{Duration, Instant};
+
+    use super::{Interrupted, POLL_INTERVAL};
+
+    pub(super) const SIGINT: i32 = libc::SIGINT;
+    pub(super) const SIGTERM: i32 = libc::SIGTERM;
+    pub(super) const SIGHUP: i32 = libc::SIGHUP;
+
+    /// How long we wait for the children to exit after forwarding a signal,
+    /// before killing them.
+    const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+    /// The last termination signal received by `cargo px`, or 0 if there was none.
+    static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+    /// The process group of the running child, if it was spawned in its own group, or 0.
+    ///
+    /// A child in our own process group receives the signals sent by the terminal alongside us,
+    /// a child in its own group must have them forwarded.
+    static CHILD_GROUP: AtomicI32 = AtomicI32::new(0);
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        RECEIVED_SIGNAL.store(signal, Ordering::SeqCst);
+    }
+
+    /// Suspend the child's process group, then ourselves.
+    extern "C" fn on_suspend(_: libc::c_int) {
+        // SAFETY: `kill`, `signal` and `raise` are async-signal-safe.
+        unsafe {
+            let group = CHILD_GROUP.load(Ordering::SeqCst);
+            if group != 0 {
+                libc::kill(-group, libc::SIGTSTP);
+            }
+            // SIGTSTP is blocked while its handler runs: the default action (stopping the
+            // process) kicks in as soon as we return.
+            libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+            libc::raise(libc::SIGTSTP);
+        }
+    }
+
+    /// Resume the child's process group once we've been resumed (e.g. via `fg`).
+    extern "C" fn on_resume(_: libc::c_int) {
+        // SAFETY: `kill` and `signal` are async-signal-safe.
+        unsafe {
+            libc::signal(libc::SIGTSTP, handler(on_suspend));
+            let group = CHILD_GROUP.load(Ordering::SeqCst);
+            if group != 0 {
+                libc::kill(-group, libc::SIGCONT);
+            }
+        }
+    }
+
+    fn handler(f: extern "C" fn(libc::c_int)) -> libc::sighandler_t {
+        f as *const () as libc::sighandler_t
+    }
+
+    fn install_signal_handlers() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            // SAFETY: the handlers only use atomics and async-signal-safe functions.
+            unsafe {
+                for signal in [SIGINT, SIGTERM, SIGHUP] {
+                    libc::signal(signal, handler(on_signal));
+                }
+                libc::signal(libc::SIGTSTP, handler(on_suspend));
+                libc::signal(libc::SIGCONT, handler(on_resume));
+            }
+        });
+    }
+
+    pub(super) fn interrupted() -> Option<i32> {
+        match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal),
+        }
+    }
+
+    /// Raise `signal` with its default disposition.
+    pub(super) fn raise(signal: i32) {
+        // SAFETY: `signal` and `raise` have no memory-safety preconditions.
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    /// Spawn `cmd`, as the leader of a new process group if `own_group` is set.
+    pub(super) fn spawn(cmd: &mut Command, own_group: bool) -> Result<Child, anyhow::Error> {
+        install_signal_handlers();
+        if let Some(signal) = interrupted() {
+            return Err(Interrupted { signal }.into());
+        }
+        if !own_group {
+            return Ok(cmd.spawn()?);
+        }
+        let child = cmd.process_group(0).spawn()?;
+        // The child is the leader of its own process group, therefore the group id
+        // matches its pid.
+        CHILD_GROUP.store(child.id() as libc::pid_t, Ordering::SeqCst);
+        Ok(child)
+    }
+
+    /// Stop forwarding signals to `child`, which has exited.
+    pub(super) fn release(_child: &Child) {
+        CHILD_GROUP.store(0, Ordering::SeqCst);
+    }
+
+    /// Send `signal` to `child` (or to its process group, if it has its own), then kill it
+    /// if it's still running once the grace period has expired.
+    pub(super) fn shut_down(
+        child: &mut Child,
+        signal: i32,
+        own_group: bool,
+    ) -> std::io::Result<()> {
+        let pid = child.id() as libc::pid_t;
+        // A negative pid targets the whole process group.
+        let target = if own_group { -pid } else { pid };
+        // SAFETY: `kill` has no memory-safety preconditions.
+        unsafe {
+            libc::kill(target, signal);
+        }
+        let deadline = Instant::now() + GRACE_PERIOD;
+        while child.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                tracing::debug!("The grace period expired, killing the child process");
+                // SAFETY: `kill` has no memory-safety preconditions.
+                unsafe {
+                    libc::kill(target, libc::SIGKILL);
+                }
+                child.wait()?;
+                break;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::os::windows::io::AsRawHandle;
+    use std::process::{Child, Command};
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject,
+    };
+
+    pub(super) const SIGINT: i32 = 2;
+    pub(super) const SIGTERM: i32 = 15;
+    pub(super) const SIGHUP: i32 = 1;
+
+    /// The job object that contains the running child and its descendants, or 0.
+    ///
+    /// Windows doesn't have process groups: a job object is how we terminate the whole
+    /// process tree (e.g. `cargo run` and the code generator it invokes) as a unit.
+    static CHILD_JOB: AtomicIsize = AtomicIsize::new(0);
+
+    // On Windows, Ctrl-C is delivered to every process attached to the console,
+    // so the children are interrupted alongside `cargo px`.
+    pub(super) fn interrupted() -> Option<i32> {
+        None
+    }
+
+    /// Spawn `cmd`, in a new job object if `own_group` is set.
+    pub(super) fn spawn(cmd: &mut Command, own_group: bool) -> Result<Child, anyhow::Error> {
+        let child = cmd.spawn()?;
+        if !own_group {
+            return Ok(child);
+        }
+        // The processes spawned by the child join its job automatically.
+        // The child may spawn processes before it's assigned to the job, but `cargo run`
+        // only launches the generator once the build is done—well after that.
+        // SAFETY: both handles are valid for the duration of the calls.
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job == 0 {
+                tracing::debug!(error = ?std::io::Error::last_os_error(), "Failed to create a job object");
+            } else if AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE) == 0 {
+                tracing::debug!(error = ?std::io::Error::last_os_error(), "Failed to assign the child process to a job object");
+                CloseHandle(job);
+            } else {
+                CHILD_JOB.store(job, Ordering::SeqCst);
+            }
+        }
+        Ok(child)
+    }
+
+    /// Release the job object of `child`, which has exited.
+    ///
+    /// Closing the handle doesn't affect the processes that are still in the job.
+    pub(super) fn release(_child: &Child) {
+        let job = CHILD_JOB.swap(0, Ordering::SeqCst);
+        if job != 0 {
+            // SAFETY: `job` is a handle we own, and it's not used after this point.
+            unsafe {
+                CloseHandle(job);
+            }
+        }
+    }
+
+    pub(super) fn raise(_signal: i32) {}
+
+    /// Terminate `child`, together with its descendants if it has its own job object.
+    pub(super) fn shut_down(
+        child: &mut Child,
+        _signal: i32,
+        own_group: bool,
+    ) -> std::io::Result<()> {
+        let job = CHILD_JOB.load(Ordering::SeqCst);
+        if own_group && job != 0 {
+            // SAFETY: `job` stays valid until `release` is called.
+            if unsafe { TerminateJobObject(job, 1) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        } else {
+            child.kill()?;
+        }
+        child.wait()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_lines_can_be_copy_pasted() {
+        let mut cmd = Command::new("cargo");
+        cmd.args(["run", "--", "it's", "a b"])
+            .env("CARGO_PX_WORKSPACE_ROOT_DIR", "/tmp/my ws")
+            .env("PATH", "/usr/bin");
+        assert_eq!(
+            command_line(&cmd),
+            r#"CARGO_PX_WORKSPACE_ROOT_DIR='/tmp/my ws' cargo run -- 'it'\''s' 'a b'"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn background_processes_holding_the_pipes_are_not_waited_for() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo hi; sleep 30 &"]);
+        let start = Instant::now();
+        let mut lines = Vec::new();
+        let status = run_captured(&mut cmd, None, |_, line| lines.push(line.to_owned())).unwrap();
+        assert!(status.success());
+        assert_eq!(lines, ["hi"]);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_is_drained_while_the_pipes_stay_busy() {
+        let mut cmd = Command::new("sh");
+        // The last line is written well after the drain would have stopped with a fixed deadline.
+        cmd.args(["-c", "(sleep 0.3; echo a; sleep 0.3; echo b) &"]);
+        let mut lines = Vec::new();
+        let status = run_captured(&mut cmd, None, |_, line| lines.push(line.to_owned())).unwrap();
+        assert!(status.success());
+        assert_eq!(lines, ["a", "b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn children_are_shut_down_when_they_time_out() {
+        let mut cmd = Command::new("sh");
+        // The trailing command stops `sh` from exec-ing into `sleep`: it has a child of its own.
+        cmd.args(["-c", "sleep 30; true"]);
+        let start = Instant::now();
+        let outcome = run_captured(&mut cmd, Some(Duration::from_millis(100)), |_, _| {});
+        let error = outcome.unwrap_err();
+        assert!(error.is::<TimedOut>(), "{error:?}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
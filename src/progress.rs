@@ -0,0 +1,86 @@
+//! A `cargo`-style progress bar, drawn at the bottom of the terminal below the
+//! usual status messages—e.g. `Generating [=====>   ] 5/12: api-client`.
+use crate::shell::{Shell, TtyWidth, Verbosity};
+
+/// Tracks the progress of a multi-step operation, such as generating all the codegen
+/// units in the current plan.
+///
+/// It's a no-op if stderr is not a terminal: the status messages are enough on their own.
+pub(crate) struct Progress {
+    /// The status header shown on the left of the bar, e.g. `Generating`.
+    header: &'static str,
+    total: usize,
+    done: usize,
+    /// The name of the step in progress.
+    current: String,
+    is_enabled: bool,
+}
+
+impl Progress {
+    pub(crate) fn new(header: &'static str, total: usize, shell: &Shell) -> Self {
+        // Like `cargo`, we don't draw progress bars in dumb terminals and on CI.
+        let is_enabled = shell.verbosity() != Verbosity::Quiet
+            && matches!(shell.err_width(), TtyWidth::Known(_))
+            && std::env::var("TERM").map_or(true, |term| term != "dumb")
+            && std::env::var_os("CI").is_none();
+        Self {
+            header,
+            total,
+            done: 0,
+            current: String::new(),
+            is_enabled,
+        }
+    }
+
+    /// Record that `done` steps have been completed and that `current` is in progress,
+    /// then redraw the bar.
+    pub(crate) fn tick(&mut self, done: usize, current: &str, shell: &mut Shell) {
+        self.done = done;
+        self.current = current.to_owned();
+        self.draw(shell);
+    }
+
+    /// Draw the bar on the last line of the terminal.
+    ///
+    /// It must be redrawn after printing a status message, since the message replaces it.
+    pub(crate) fn draw(&self, shell: &mut Shell) {
+        if !self.is_enabled {
+            return;
+        }
+        let TtyWidth::Known(width) = shell.err_width() else {
+            return;
+        };
+        let counter = format!(" {}/{}", self.done, self.total);
+        // The status header is right-aligned to 12 characters, followed by a space.
+        let available = width.saturating_sub(13 + counter.len() + 3);
+        let bar_width = available.min(60);
+        if bar_width < 10 {
+            return;
+        }
+        let filled = bar_width * self.done / self.total.max(1);
+        let mut bar = "=".repeat(filled);
+        if filled < bar_width {
+            bar.push('>');
+            bar.push_str(&" ".repeat(bar_width - filled - 1));
+        }
+        let mut line = format!("[{bar}]{counter}");
+        let remaining = width.saturating_sub(13 + line.len() + 3);
+        if !self.current.is_empty() && remaining > 0 {
+            let name: String = self.current.chars().take(remaining).collect();
+            line.push_str(": ");
+            line.push_str(&name);
+        }
+
+        shell.set_needs_clear(false);
+        let _ = shell.status_header(self.header);
+        let _ = write!(shell.err(), "{line}\r");
+        shell.set_needs_clear(true);
+    }
+
+    /// Remove the bar, e.g. before handing over the terminal to a child process.
+    pub(crate) fn clear(&self, shell: &mut Shell) {
+        if !shell.is_cleared() {
+            shell.err_erase_line();
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Simulate `cargo`'s feature resolution to determine which packages will actually be built
+//! by the current invocation.
+use ahash::{HashSet, HashSetExt};
+use guppy::{
+    graph::{
+        cargo::{CargoOptions, CargoResolverVersion},
+        feature::FeatureId,
+        DependencyDirection, PackageGraph, PackageLink,
+    },
+    platform::{EnabledTernary, Platform, PlatformSpec, TargetFeatures},
+    PackageId,
+};
+
+use crate::cargo_args::{flag_values, has_flag};
+use crate::cargo_config::CargoConfig;
+
+/// The feature selection flags passed to `cargo`.
+#[derive(Debug, Default)]
+pub(crate) struct FeatureSelection {
+    /// The features passed via `-F`/`--features`.
+    ///
+    /// They are either unqualified (e.g. `foo`) or qualified with a package name (e.g. `bar/foo`).
+    features: Vec<String>,
+    /// `--all-features`
+    all_features: bool,
+    /// `--no-default-features`
+    no_default_features: bool,
+}
+
+impl FeatureSelection {
+    /// Extract the feature selection flags from the arguments of a `cargo` invocation.
+    pub(crate) fn from_args(args: &[String]) -> Self {
+        let features = flag_values(args, Some('F'), "features")
+            .iter()
+            .flat_map(|value| value.split([',', ' ']))
+            .filter(|feature| !feature.is_empty())
+            .map(ToOwned::to_owned)
+            .collect();
+        Self {
+            features,
+            all_features: has_flag(args, None, "all-features"),
+            no_default_features: has_flag(args, None, "no-default-features"),
+        }
+    }
+}
+
+/// The platforms that `cargo` is going to build for.
+#[derive(Debug, Clone)]
+pub(crate) struct TargetPlatforms {
+    /// The platform `cargo` is running on, where build scripts and proc-macros are built.
+    host: PlatformSpec,
+    /// The platforms the target packages are built for.
+    ///
+    /// It's just the host platform, unless `--target` or `build.target` have been specified.
+    targets: Vec<PlatformSpec>,
+}
+
+impl TargetPlatforms {
+    /// Determine the target platforms from `--target` or, if it's missing, from
+    /// the `build.target` configuration key.
+    ///
+    /// Platforms that we can't reason about (e.g. custom target specs defined in a JSON file)
+    /// are treated as if they could enable any platform-specific dependency.
+    pub(crate) fn new(args: &[String], config: &CargoConfig) -> Self {
+        let host = PlatformSpec::current().unwrap_or(PlatformSpec::Any);
+        let mut triples = flag_values(args, None, "target");
+        if triples.is_empty() {
+            triples = match config.get("build.target") {
+                Some(toml::Value::String(triple)) => vec![triple],
+                Some(toml::Value::Array(triples)) => triples
+                    .into_iter()
+                    .filter_map(|triple| triple.as_str().map(ToOwned::to_owned))
+                    .collect(),
+                _ => vec![],
+            };
+        }
+        let targets = if triples.is_empty() {
+            vec![host.clone()]
+        } else {
+            triples
+                .iter()
+                .map(|triple| {
+                    if triple == "host-tuple" {
+                        return host.clone();
+                    }
+                    match Platform::new(triple.to_owned(), TargetFeatures::Unknown) {
+                        Ok(platform) => PlatformSpec::from(platform),
+                        Err(e) => {
+                            tracing::debug!(error = ?e, %triple, "Unknown target platform");
+                            PlatformSpec::Any
+                        }
+                    }
+                })
+                .collect()
+        };
+        Self { host, targets }
+    }
+
+    /// The platforms to use for packages that are built for the host—e.g. code generators,
+    /// which are executed by `cargo px` on the current machine.
+    pub(crate) fn host_only(&self) -> Self {
+        Self {
+            host: self.host.clone(),
+            targets: vec![self.host.clone()],
+        }
+    }
+
+    /// Check if `link` may be followed by `cargo` when building for these platforms.
+    ///
+    /// Build dependencies are evaluated against the host platform, while normal and
+    /// dev-dependencies are evaluated against the target platforms.
+    pub(crate) fn is_enabled(&self, link: &PackageLink, include_dev: bool) -> bool {
+        let is_enabled = |status: guppy::graph::EnabledStatus, platform: &PlatformSpec| {
+            status.enabled_on(platform) != EnabledTernary::Disabled
+        };
+        self.targets.iter().any(|target| {
+            is_enabled(link.normal().status(), target)
+                || (include_dev && is_enabled(link.dev().status(), target))
+        }) || is_enabled(link.build().status(), &self.host)
+    }
+}
+
+/// Return the set of packages that `cargo` will build when asked to build `targets`
+/// with the given feature selection, for the given platforms.
+///
+/// If `include_dev` is set, the dev-dependencies of `targets` are included as well.
+///
+/// We use the V1 feature resolver, which unifies features more aggressively than V2.
+/// The resulting set may therefore include a few packages that `cargo` won't actually build,
+/// but it'll never miss one that it does build—a stale generated crate is worse than a
+/// redundant code generation step.
+pub(crate) fn packages_to_build(
+    package_graph: &PackageGraph,
+    targets: &[PackageId],
+    features: &FeatureSelection,
+    include_dev: bool,
+    platforms: &TargetPlatforms,
+) -> Result<HashSet<PackageId>, anyhow::Error> {
+    let feature_graph = package_graph.feature_graph();
+    let mut initials = Vec::new();
+    for target_id in targets {
+        let target = package_graph.metadata(target_id)?;
+        initials.push(FeatureId::base(target_id));
+        if features.all_features {
+            initials.extend(
+                target
+                    .named_features()
+                    .map(|feature| FeatureId::named(target_id, feature)),
+            );
+        } else if !features.no_default_features {
+            initials.push(FeatureId::named(target_id, "default"));
+        }
+
+        for feature in &features.features {
+            match feature.split_once('/') {
+                None => initials.push(FeatureId::named(target_id, feature)),
+                Some((package_name, feature)) if package_name == target.name() => {
+                    initials.push(FeatureId::named(target_id, feature))
+                }
+                // `dep_name/feature` enables `feature` on the `dep_name` dependency of the target.
+                Some((dep_name, feature)) => {
+                    for link in target.direct_links_directed(DependencyDirection::Forward) {
+                        if link.dep_name() == dep_name {
+                            initials.push(FeatureId::named(link.to().id(), feature));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    // `cargo` rejects unknown features, but it's not our job to report them.
+    initials.retain(|feature_id| feature_graph.contains(*feature_id));
+
+    let initials = feature_graph.resolve_ids(initials)?;
+
+    let mut package_ids = HashSet::new();
+    for target_platform in &platforms.targets {
+        let mut opts = CargoOptions::new();
+        opts.set_resolver(CargoResolverVersion::V1)
+            .set_include_dev(include_dev)
+            .set_host_platform(platforms.host.clone())
+            .set_target_platform(target_platform.clone());
+        let cargo_set = initials.clone().into_cargo_set(&opts)?;
+        for (_, feature_set) in cargo_set.all_features() {
+            package_ids.extend(
+                feature_set
+                    .to_package_set()
+                    .package_ids(DependencyDirection::Forward)
+                    .cloned(),
+            );
+        }
+    }
+    Ok(package_ids)
+}
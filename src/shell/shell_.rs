@@ -620,7 +620,7 @@ fn default_err_erase_line(shell: &mut Shell) {
     }
 }
 
-mod style {
+pub(crate) mod style {
     use anstyle::{AnsiColor, Effects, Style};
 
     pub const HEADER: Style = AnsiColor::Green.on_default().effects(Effects::BOLD);
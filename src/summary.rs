@@ -0,0 +1,169 @@
+//! A summary of the code generation work performed by `cargo px`, printed at the end of
+//! the run in verbose mode.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::fingerprint;
+use crate::shell::Shell;
+
+/// Generators that run for longer than this are highlighted in the summary.
+const SLOW_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// What happened to a codegen unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum UnitOutcome {
+    Generated,
+    /// The inputs of the generator haven't changed since its last run: it wasn't invoked.
+    Fresh,
+    /// Code generation wasn't attempted, e.g. because a previous unit failed.
+    Skipped,
+    Failed,
+}
+
+impl std::fmt::Display for UnitOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnitOutcome::Generated => write!(f, "generated"),
+            UnitOutcome::Fresh => write!(f, "fresh"),
+            UnitOutcome::Skipped => write!(f, "skipped"),
+            UnitOutcome::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// The work performed for a single codegen unit.
+#[derive(Debug)]
+pub(crate) struct UnitReport {
+    /// The name of the generated package.
+    pub(crate) name: String,
+    pub(crate) outcome: UnitOutcome,
+    /// How long it took to compile the generator.
+    pub(crate) build_time: Option<Duration>,
+    /// How long it took to run the generator.
+    pub(crate) run_time: Option<Duration>,
+    /// How many files were created, modified or deleted by the generator.
+    pub(crate) changed_files: Option<usize>,
+}
+
+impl UnitReport {
+    pub(crate) fn new(name: &str, outcome: UnitOutcome) -> Self {
+        Self {
+            name: name.to_owned(),
+            outcome,
+            build_time: None,
+            run_time: None,
+            changed_files: None,
+        }
+    }
+
+    fn is_slow(&self) -> bool {
+        self.run_time.is_some_and(|t| t >= SLOW_THRESHOLD)
+    }
+}
+
+/// The reports for all the codegen units in the plan, in execution order.
+#[derive(Debug, Default)]
+pub(crate) struct Summary {
+    reports: Vec<UnitReport>,
+}
+
+impl Summary {
+    pub(crate) fn push(&mut self, report: UnitReport) {
+        self.reports.push(report);
+    }
+
+    /// Print the summary as a table, one row per unit.
+    ///
+    /// Every row starts with the `Summary` status header, to make it easy to grep for in CI logs.
+    pub(crate) fn print(&self, shell: &mut Shell) {
+        if self.reports.is_empty() {
+            return;
+        }
+        let name_width = self
+            .reports
+            .iter()
+            .map(|r| r.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("unit".len());
+        let row = |name: &str, outcome: &str, build: &str, run: &str, changed: &str| {
+            format!("{name:<name_width$}  {outcome:<9}  {build:>9}  {run:>9}  {changed:>7}")
+        };
+        let _ = shell.status("Summary", row("unit", "status", "build", "run", "changed"));
+        let format_duration = |t: Option<Duration>| {
+            t.map_or_else(|| "-".to_owned(), |t| format!("{:.3}s", t.as_secs_f32()))
+        };
+        for report in &self.reports {
+            let line = row(
+                &report.name,
+                &report.outcome.to_string(),
+                &format_duration(report.build_time),
+                &format_duration(report.run_time),
+                &report
+                    .changed_files
+                    .map_or_else(|| "-".to_owned(), |n| n.to_string()),
+            );
+            if report.is_slow() {
+                let _ = shell.status_with_color(
+                    "Summary",
+                    format!("{line}  (slow)"),
+                    &crate::shell::style::WARN,
+                );
+            } else {
+                let _ = shell.status("Summary", line);
+            }
+        }
+    }
+}
+
+/// The content of the files in a directory, as a map from path to a hash of the content.
+pub(crate) type Snapshot = HashMap<PathBuf, u64>;
+
+/// Take a snapshot of the files in `dir`, recursively.
+///
+/// We skip `target` and hidden directories (e.g. `.git`), as well as nested packages
+/// (e.g. other workspace members): they are not part of the package in `dir`.
+/// Files that can't be read are ignored.
+pub(crate) fn snapshot(dir: &Path) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => {
+                    let name = entry.file_name();
+                    let is_hidden = name.to_string_lossy().starts_with('.');
+                    if name != "target" && !is_hidden && !path.join("Cargo.toml").exists() {
+                        pending.push(path);
+                    }
+                }
+                Ok(file_type) if file_type.is_file() => {
+                    let Ok(content) = std::fs::read(&path) else {
+                        continue;
+                    };
+                    snapshot.insert(path, fingerprint::hash(&content));
+                }
+                _ => {}
+            }
+        }
+    }
+    snapshot
+}
+
+/// Count the files that have been created, modified or deleted between two snapshots.
+pub(crate) fn changed_files(before: &Snapshot, after: &Snapshot) -> usize {
+    let created_or_modified = after
+        .iter()
+        .filter(|(path, hash)| before.get(*path) != Some(*hash))
+        .count();
+    let deleted = before
+        .keys()
+        .filter(|path| !after.contains_key(*path))
+        .count();
+    created_or_modified + deleted
+}
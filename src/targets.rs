@@ -1,26 +1,110 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use clap::{Arg, Command};
-use guppy::{graph::PackageGraph, PackageId};
+use guppy::{
+    graph::{BuildTarget, BuildTargetId, PackageGraph},
+    PackageId,
+};
+
+use crate::cargo_args::{flag_value, flag_values, has_flag};
+use crate::package_spec::PackageSpec;
+use crate::Shell;
 
 /// Determine which sub-units should be built from the package graph.
 ///
 /// We implement a simplified version of the general algorithm in `cargo`. We determine the target packages based on:
 ///
-/// - The `-p`/`--package` flag, which specifies a list of package specs to be considered.
-/// - The current working directory, if no package specs are specified.
+/// - The `--workspace`/`--all` flag, which selects every workspace member except the ones
+///   matched by `--exclude`.
+/// - The `-p`/`--package` flag, which specifies a list of package specs (or glob patterns) to be considered.
+/// - The `--manifest-path` flag (or `--path`, for `cargo install`) or, if it's missing, the
+///   current working directory, if no package specs are specified. If they point at the
+///   workspace root, we use the workspace's default members, just like `cargo`.
+/// - The target selection flags (`--lib`, `--bin`, `--example`, `--test` and `--bench`), which
+///   narrow down the selected packages to those that own the requested targets.
+///
+/// Package specs that don't match any workspace member are reported as a warning and otherwise ignored.
 ///
-/// But we assume that the specified package specs refer to packages in the workspace. If not, we fall back to performing
-/// codegen for everything.
+/// It returns `None` if the targets couldn't be narrowed down—i.e. codegen should be performed for everything.
 pub(crate) fn determine_targets(
     args: &[String],
     working_directory: &Path,
     package_graph: &PackageGraph,
-) -> Vec<PackageId> {
-    // TODO: Handle other forms of package selection in `cargo`:
-    //   - --workspace / --exclude
-    //   - --manifest-path
-    //   - Target selection via --bin/--lib/etc.
+    default_members: Option<&[PackageId]>,
+    shell: &mut Shell,
+) -> Option<Vec<PackageId>> {
+    let selected = select_packages(
+        args,
+        working_directory,
+        package_graph,
+        default_members,
+        shell,
+    );
+
+    let target_filters = extract_target_filters(args, shell);
+    if target_filters.is_empty() {
+        return selected;
+    }
+    tracing::debug!(
+        ?target_filters,
+        "Extracted the following target filters for this invocation"
+    );
+
+    // Keep only the packages that own at least one of the requested targets.
+    let candidates = match selected {
+        Some(package_ids) => package_ids,
+        None => package_graph.workspace().member_ids().cloned().collect(),
+    };
+    let package_ids: Vec<_> = candidates
+        .into_iter()
+        .filter(|id| {
+            let Ok(package) = package_graph.metadata(id) else {
+                return false;
+            };
+            package
+                .build_targets()
+                .any(|target| target_filters.iter().any(|f| f.matches(&target)))
+        })
+        .collect();
+    if package_ids.is_empty() {
+        let _ = shell.warn(
+            "None of the selected packages own the requested targets. \
+            `cargo px` will not perform code generation",
+        );
+    }
+    Some(package_ids)
+}
+
+/// Determine the target packages based on the package selection flags.
+fn select_packages(
+    args: &[String],
+    working_directory: &Path,
+    package_graph: &PackageGraph,
+    default_members: Option<&[PackageId]>,
+    shell: &mut Shell,
+) -> Option<Vec<PackageId>> {
+    // Skip `px <sub_command>`
+    if has_flag(&args[2..], None, "workspace") || has_flag(&args[2..], None, "all") {
+        let excluded: Vec<_> = flag_values(&args[2..], None, "exclude")
+            .iter()
+            .filter_map(|raw_spec| match PackageSpec::parse(raw_spec) {
+                Ok(spec) => Some(spec),
+                Err(e) => {
+                    let _ = shell.warn(format!(
+                        "{e:#}. `cargo px` will not exclude it from code generation"
+                    ));
+                    None
+                }
+            })
+            .collect();
+        tracing::debug!(?excluded, "Selecting all workspace members");
+        let package_ids = package_graph
+            .workspace()
+            .iter()
+            .filter(|package| !excluded.iter().any(|spec| spec.matches(package)))
+            .map(|package| package.id().clone())
+            .collect();
+        return Some(package_ids);
+    }
 
     let package_specs = extract_package_filters(args);
 
@@ -36,23 +120,59 @@ pub(crate) fn determine_targets(
     }
 
     if package_specs.is_empty() {
-        return find_implicit_target(working_directory, package_graph)
-            .map(|id| vec![id])
-            .unwrap_or_default();
+        // `cargo` selects the package whose manifest was passed via `--manifest-path`,
+        // exactly as if it had been invoked from the directory that contains it.
+        let manifest_dir = extract_manifest_path(args, working_directory)
+            .and_then(|path| path.parent().map(ToOwned::to_owned));
+        let working_directory = manifest_dir.as_deref().unwrap_or(working_directory);
+        let implicit_target = find_implicit_target(working_directory, package_graph);
+
+        // If the closest manifest is the workspace root, `cargo` operates on the default members
+        // of the workspace.
+        let is_workspace_root = match &implicit_target {
+            None => true,
+            Some(id) => package_graph
+                .metadata(id)
+                .ok()
+                .and_then(|package| package.source().workspace_path())
+                .is_some_and(|path| path.as_str().is_empty()),
+        };
+        if is_workspace_root {
+            if let Some(default_members) = default_members {
+                tracing::debug!("Using the default members of the workspace as targets");
+                return Some(default_members.to_vec());
+            }
+        }
+        return implicit_target.map(|id| vec![id]);
     }
 
     // Collect the package IDs for the specified package specs.
     let mut package_ids = Vec::new();
-    for spec in package_specs {
-        if let Ok(package) = package_graph.workspace().member_by_name(&spec) {
-            package_ids.push(package.id().clone());
-        } else {
-            // If any spec does not match a workspace package, fall back to performing codegen for everything.
-            return vec![];
+    for raw_spec in package_specs {
+        let spec = match PackageSpec::parse(&raw_spec) {
+            Ok(spec) => spec,
+            Err(e) => {
+                let _ = shell.warn(format!(
+                    "{e:#}. `cargo px` will not perform code generation on its behalf"
+                ));
+                continue;
+            }
+        };
+        let n_matches_before = package_ids.len();
+        for package in package_graph.workspace().iter() {
+            if spec.matches(&package) && !package_ids.contains(package.id()) {
+                package_ids.push(package.id().clone());
+            }
+        }
+        if package_ids.len() == n_matches_before {
+            let _ = shell.warn(format!(
+                "`{raw_spec}` did not match any package in the workspace. \
+                `cargo px` will not perform code generation on its behalf"
+            ));
         }
     }
 
-    package_ids
+    Some(package_ids)
 }
 
 /// If no package specs have been provided, determine the package based on the working directory.
@@ -81,28 +201,107 @@ fn find_implicit_target(
         .map(|(package_metadata, _)| package_metadata.id().to_owned())
 }
 
+/// Check if the current invocation compiles targets that have access to dev-dependencies—i.e.
+/// tests, examples and benchmarks.
+pub(crate) fn needs_dev_dependencies(args: &[String]) -> bool {
+    let Some(mut command) = args.get(1) else {
+        return false;
+    };
+    // `cargo miri test` and `cargo hack test` compile what the nested command compiles.
+    if ["miri", "hack"].contains(&command.as_str()) {
+        let Some(nested) = args.get(2) else {
+            return false;
+        };
+        command = nested;
+    }
+    if ["test", "t", "bench", "nextest", "llvm-cov", "tarpaulin"].contains(&command.as_str()) {
+        return true;
+    }
+    // Skip `px <sub_command>`
+    let args = &args[2..];
+    ["tests", "benches", "examples", "all-targets"]
+        .iter()
+        .any(|flag| has_flag(args, None, flag))
+        || ["test", "bench", "example"]
+            .iter()
+            .any(|flag| !flag_values(args, None, flag).is_empty())
+}
+
+/// A filter on the build targets that `cargo` should operate on, e.g. `--bin server`.
+#[derive(Debug)]
+enum TargetFilter {
+    Lib,
+    Bin(glob::Pattern),
+    Example(glob::Pattern),
+    Test(glob::Pattern),
+    Bench(glob::Pattern),
+}
+
+impl TargetFilter {
+    fn matches(&self, target: &BuildTarget) -> bool {
+        match (self, target.id()) {
+            (TargetFilter::Lib, BuildTargetId::Library) => true,
+            (TargetFilter::Bin(pattern), BuildTargetId::Binary(name))
+            | (TargetFilter::Example(pattern), BuildTargetId::Example(name))
+            | (TargetFilter::Test(pattern), BuildTargetId::Test(name))
+            | (TargetFilter::Bench(pattern), BuildTargetId::Benchmark(name)) => {
+                pattern.matches(name)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Check if the user has asked `cargo` to operate on specific build targets.
+///
+/// Just like `cargo`, we accept glob patterns as target names.
+fn extract_target_filters(args: &[String], shell: &mut Shell) -> Vec<TargetFilter> {
+    // Skip `px <sub_command>`
+    let args = &args[2..];
+    let mut filters = Vec::new();
+    if has_flag(args, None, "lib") {
+        filters.push(TargetFilter::Lib);
+    }
+    for flag in ["bin", "example", "test", "bench"] {
+        for name in flag_values(args, None, flag) {
+            let pattern = match glob::Pattern::new(&name) {
+                Ok(pattern) => pattern,
+                Err(e) => {
+                    let _ = shell.warn(format!(
+                        "`{name}` is not a valid target name for `--{flag}`: {e}"
+                    ));
+                    continue;
+                }
+            };
+            filters.push(match flag {
+                "bin" => TargetFilter::Bin(pattern),
+                "example" => TargetFilter::Example(pattern),
+                "test" => TargetFilter::Test(pattern),
+                _ => TargetFilter::Bench(pattern),
+            });
+        }
+    }
+    filters
+}
+
 /// Check if the user has specified a list of package specs to be considered.
 fn extract_package_filters(args: &[String]) -> Vec<String> {
-    let Ok(matches) = Command::new("px")
-        .no_binary_name(true)
-        .arg(
-            Arg::new("package")
-                .short('p')
-                .long("package")
-                .num_args(1)
-                .action(clap::ArgAction::Append)
-                .help("Package(s) to operate on"),
-        )
-        .allow_external_subcommands(true)
-        .dont_collapse_args_in_usage(true)
-        // Skip `px <sub_command>`
-        .try_get_matches_from(&args[2..])
-    else {
-        tracing::debug!("Failed to match `-p`/`--package` arguments");
-        return Vec::new();
+    // Skip `px <sub_command>`
+    flag_values(&args[2..], Some('p'), "package")
+}
+
+/// Check if the user has specified the path to the manifest that `cargo` should operate on.
+///
+/// `cargo install --path <dir>` is treated as `--manifest-path <dir>/Cargo.toml`.
+/// Relative paths are resolved against the working directory.
+pub(crate) fn extract_manifest_path(args: &[String], working_directory: &Path) -> Option<PathBuf> {
+    // Skip `px <sub_command>`
+    let manifest_path = match flag_value(&args[2..], None, "manifest-path") {
+        Some(manifest_path) => working_directory.join(manifest_path),
+        None if args.get(1).is_some_and(|command| command == "install") => working_directory
+            .join(flag_value(&args[2..], None, "path")?)
+            .join("Cargo.toml"),
+        None => return None,
     };
-    matches
-        .get_many::<String>("package")
-        .map(|vals| vals.cloned().collect())
-        .unwrap_or_default()
+    Some(manifest_path.canonicalize().unwrap_or(manifest_path))
 }
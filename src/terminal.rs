@@ -0,0 +1,95 @@
+//! Configure `cargo px`'s output following `cargo`'s
+//! [terminal settings](https://doc.rust-lang.org/cargo/reference/config.html#term).
+use std::path::Path;
+
+use crate::cargo_args::{flag_value, has_flag};
+use crate::cargo_config::CargoConfig;
+use crate::shell::{ColorChoice, Shell, Verbosity};
+
+/// Configure `shell` using the same sources, and the same precedence rules, as `cargo`:
+/// command-line flags (`--color`, `-v`/`-vv`, `-q`) win over the `term.*` configuration keys,
+/// which can in turn be set via environment variables (e.g. `CARGO_TERM_COLOR`).
+pub(crate) fn configure_shell(
+    shell: &mut Shell,
+    args: &[String],
+    working_directory: &Path,
+) -> Result<(), anyhow::Error> {
+    let config = CargoConfig::load(working_directory);
+
+    let verbose = verbose_count(args) > 0;
+    let quiet = has_flag(args, Some('q'), "quiet");
+    let verbosity = match (verbose, quiet) {
+        (true, true) => anyhow::bail!("cannot set both --verbose and --quiet"),
+        (true, false) => Verbosity::Verbose,
+        (false, true) => Verbosity::Quiet,
+        (false, false) => {
+            match (
+                config_bool(&config, "term.verbose"),
+                config_bool(&config, "term.quiet"),
+            ) {
+                (Some(true), Some(true)) => {
+                    anyhow::bail!("cannot set both `term.verbose` and `term.quiet`")
+                }
+                (Some(true), _) => Verbosity::Verbose,
+                (_, Some(true)) => Verbosity::Quiet,
+                _ => Verbosity::Normal,
+            }
+        }
+    };
+    shell.set_verbosity(verbosity);
+
+    let color = flag_value(args, None, "color").or_else(|| match config.get("term.color") {
+        Some(toml::Value::String(color)) => Some(color),
+        _ => None,
+    });
+    shell.set_color_choice(color.as_deref())?;
+
+    if let Some(hyperlinks) = config_bool(&config, "term.hyperlinks") {
+        shell.set_hyperlinks(hyperlinks)?;
+    }
+    Ok(())
+}
+
+/// The value to assign to `CARGO_TERM_COLOR` for the processes we spawn, so that they
+/// follow the same color choice as `cargo px`.
+///
+/// `auto` is resolved against our own stderr: the output of generators is captured,
+/// so they can't tell if they're writing to a terminal.
+pub(crate) fn color_env(shell: &Shell) -> &'static str {
+    match shell.color_choice() {
+        ColorChoice::Always => "always",
+        ColorChoice::Never => "never",
+        ColorChoice::CargoAuto if shell.err_supports_color() => "always",
+        ColorChoice::CargoAuto => "never",
+    }
+}
+
+/// How many times `-v`/`--verbose` was passed. `-vv` counts twice.
+fn verbose_count(args: &[String]) -> usize {
+    args.iter()
+        .take_while(|arg| *arg != "--")
+        .map(|arg| match arg.as_str() {
+            "--verbose" => 1,
+            short if short.len() > 1 && short.starts_with('-') && !short.starts_with("--") => {
+                let flags = &short[1..];
+                if flags.chars().all(|c| c == 'v') {
+                    flags.len()
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Read a boolean configuration key.
+///
+/// Values coming from environment variables are strings, so we accept `"true"`/`"false"` too.
+fn config_bool(config: &CargoConfig, key: &str) -> Option<bool> {
+    match config.get(key)? {
+        toml::Value::Boolean(value) => Some(value),
+        toml::Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
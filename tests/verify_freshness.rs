@@ -0,0 +1,104 @@
+//! `cargo px verify-freshness` must check every codegen unit in the workspace when invoked
+//! from the root, ignoring default members, and only the current member otherwise.
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Create an empty directory, unique to the calling test.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cargo-px-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Create a workspace in a fresh temporary directory.
+fn workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = scratch_dir(name);
+    for (path, content) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    root
+}
+
+fn cargo_px(working_directory: &Path, args: &[&str]) -> std::process::Output {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    Command::new(env!("CARGO_BIN_EXE_cargo-px"))
+        .arg("px")
+        .args(args)
+        .current_dir(working_directory)
+        .env("CARGO", cargo)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn verify_freshness_ignores_default_members() {
+    // `other` is the only default member and it only dev-depends on the generated crate.
+    let root = workspace(
+        "default-members",
+        &[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["gen", "api", "other"]
+default-members = ["other"]
+resolver = "2"
+"#,
+            ),
+            (
+                "gen/Cargo.toml",
+                "[package]\nname = \"gen\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+            ),
+            ("gen/src/main.rs", "fn main() {}\n"),
+            (
+                "api/Cargo.toml",
+                r#"[package]
+name = "api"
+version = "0.1.0"
+edition = "2021"
+
+[package.metadata.px.generate]
+generator_type = "cargo_workspace_binary"
+generator_name = "gen"
+"#,
+            ),
+            ("api/src/lib.rs", ""),
+            (
+                "other/Cargo.toml",
+                r#"[package]
+name = "other"
+version = "0.1.0"
+edition = "2021"
+
+[dev-dependencies]
+api = { path = "../api" }
+"#,
+            ),
+            ("other/src/lib.rs", ""),
+        ],
+    );
+
+    // From the root, every unit is checked—even if default members are defined.
+    let output = cargo_px(&root, &["verify-freshness"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("`api` doesn't define a verifier"),
+        "`api` should have been checked:\n{stderr}"
+    );
+
+    // From a member directory, only that member is checked.
+    let output = cargo_px(&root.join("other"), &["verify-freshness"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    let output = cargo_px(&root.join("api"), &["verify-freshness"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("`api` doesn't define a verifier"),
+        "`api` should have been checked:\n{stderr}"
+    );
+
+    let _ = std::fs::remove_dir_all(&root);
+}